@@ -1,4 +1,6 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::mirror::DEFAULT_MIRROR;
 
 #[derive(Parser)]
 #[command(
@@ -24,4 +26,233 @@ pub struct Cli {
     help = "A YAML file containing dependencies.",
     )]
   pub dependency_file: String,
+  #[arg(
+    env,
+    long,
+    default_value = "https://updates.jenkins.io/current/plugin-versions.json",
+    help = "A URL or file path of the update center's plugin-versions.json, \
+            used to pick versions for constraints that are not exact.",
+  )]
+  pub plugin_versions: String,
+  #[arg(
+    env,
+    long,
+    value_delimiter = ',',
+    default_value = DEFAULT_MIRROR,
+    help = "URL template to download plugins from.  Repeat it (or separate \
+            with commas) to fall back to other mirrors in order.  Supports \
+            {name}, {version}, {groupId} and {groupPath}, such as \
+            https://repo.example.com/jenkins/{groupPath}/{name}/{version}/{name}-{version}.hpi \
+            for a Maven layout.",
+  )]
+  pub mirror: Vec<String>,
+  #[arg(
+    env,
+    long,
+    default_value_t = 3,
+    help = "How many times to retry a download after a timeout or server \
+            error, backing off exponentially.",
+  )]
+  pub retries: u32,
+  #[arg(
+    env,
+    long,
+    help = "A URL or file path of an update-center.json or \
+            plugin-versions.json.  Every plugin archive is checked against \
+            the sha256 published there, and a mismatch or a missing version \
+            of a listed plugin is an error.  update-center.json only lists \
+            the latest version of each plugin.",
+  )]
+  pub checksums: Option<String>,
+  #[arg(
+    env,
+    short,
+    long,
+    default_value_t = 8,
+    help = "How many plugins to download at once.",
+  )]
+  pub jobs: usize,
+  #[arg(
+    env,
+    long,
+    help = "When plugins depend on each other in a cycle, drop the dependency \
+            that closes the cycle and carry on instead of failing.",
+  )]
+  pub break_cycles: bool,
+  #[arg(
+    env,
+    long,
+    value_enum,
+    default_value_t = OptionalDependencies::IfRequired,
+    help = "How to treat dependencies marked resolution:=optional.",
+  )]
+  pub optional_dependencies: OptionalDependencies,
+  #[arg(
+    env,
+    long,
+    value_enum,
+    default_value_t = ConflictPolicy::Highest,
+    help = "What to do when a plugin is asked for at different versions.",
+  )]
+  pub conflict_policy: ConflictPolicy,
+  #[arg(
+    env,
+    long,
+    value_enum,
+    default_value_t = Solver::Greedy,
+    help = "How to pick versions.  The backtracking solver needs \
+            --plugin-versions, and takes --jenkins-version into account \
+            while picking.",
+  )]
+  pub solver: Solver,
+  #[arg(
+    env,
+    long,
+    help = "The Jenkins core version the plugins will run on.  Resolution \
+            fails if any resolved plugin needs a newer core.",
+  )]
+  pub jenkins_version: Option<String>,
+  #[arg(
+    env,
+    long,
+    requires = "jenkins_version",
+    help = "Only warn about plugins needing a newer core than \
+            --jenkins-version, instead of failing.",
+  )]
+  pub allow_incompatible_core: bool,
+  #[arg(
+    env,
+    long,
+    help = "Include the minimum Jenkins core version needed by the resolved \
+            plugins in the output, for the formats with room for it.",
+  )]
+  pub emit_minimum_core: bool,
+  #[arg(
+    env,
+    long,
+    value_enum,
+    default_value_t = OutputFormat::Hiera,
+    help = "How to print the resolved plugins when no command is given.",
+  )]
+  pub format: OutputFormat,
+  #[arg(
+    env,
+    long,
+    help = "With --format helm, also set installLatestPlugins and \
+            installLatestSpecifiedPlugins to false, so the chart doesn't \
+            upgrade the resolved plugins.",
+  )]
+  pub helm_disable_latest: bool,
+  #[arg(
+    env,
+    short,
+    long,
+    help = "Write to this file instead of stdout.  The file is replaced in \
+            one go, so nothing ever sees it half written.",
+  )]
+  pub output: Option<String>,
+  #[arg(
+    env,
+    long,
+    requires = "output",
+    help = "Instead of writing --output, compare it with what would be \
+            written, and fail with a diff if they differ.",
+  )]
+  pub check: bool,
+  #[command(subcommand)]
+  pub command: Option<Command>,
+}
+
+// Without a command, the resolved plugins are printed as Hiera data.
+#[derive(Subcommand)]
+pub enum Command {
+  /// Print the minimum Jenkins core version needed by the resolved plugins,
+  /// and which plugins need it.
+  MinimumCore,
+  /// List every plugin that was asked for at more than one version, who asked
+  /// for each version, and which one won.
+  Conflicts {
+    #[arg(long, help = "Print the report as JSON.")]
+    json: bool,
+  },
+  /// Print every dependency chain from our input down to a plugin, with the
+  /// version each step asked for.
+  Why {
+    plugin: String,
+  },
+  /// Print the dependency graph as a tree, with the version each plugin was
+  /// asked for when it differs from the version it resolved to.
+  Tree {
+    #[arg(
+      long,
+      value_name = "PLUGIN",
+      help = "Put this plugin at the top and what depends on it below.",
+    )]
+    invert: Option<String>,
+    #[arg(long, help = "How many levels below the top to print.")]
+    depth: Option<usize>,
+  },
+  /// Print the dependency graph for other tools to draw or read.
+  Graph {
+    #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+    format: GraphFormat,
+  },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+  /// Hiera data for the puppet-jenkins module.
+  Hiera,
+  /// A plugins.txt of name:version lines, for jenkins-plugin-cli and the
+  /// Docker image.
+  PluginsTxt,
+  /// A plugins.yaml for the plugin installation manager.
+  PluginsYaml,
+  /// The resolved plugins as JSON.
+  Json,
+  /// A Nix expression of fetchurl calls, for services.jenkins.plugins.
+  Nix,
+  /// A values.yaml fragment setting controller.installPlugins for the
+  /// Jenkins Helm chart.
+  Helm,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum GraphFormat {
+  /// Graphviz DOT, with our inputs highlighted and optional edges dashed.
+  Dot,
+  /// JSON with a node per plugin and an edge per dependency.  The schema is
+  /// described in the README.
+  Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum ConflictPolicy {
+  /// Use the highest version anything asks for.
+  Highest,
+  /// Fail if anything asks for different versions of the same plugin.
+  Fail,
+  /// Keep the versions from our input as they are, and fail if a plugin needs
+  /// something newer than one of them.  Anything else uses the highest.
+  Pinned,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Solver {
+  /// Walk the graph, take the highest version anything asks for, and walk
+  /// again until nothing changes.
+  Greedy,
+  /// Search the published versions for a set where every constraint holds,
+  /// backing out of picks that lead to a dead end.
+  Backtracking,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum OptionalDependencies {
+  /// Leave optional dependencies out entirely.
+  Exclude,
+  /// Include an optional dependency only if something else requires it.
+  IfRequired,
+  /// Treat optional dependencies like any other.
+  All,
 }
@@ -0,0 +1,314 @@
+// When more than one version of a plugin is asked for, resolution quietly keeps
+// one of them.  The conflict report lists who asked for what, so a reviewer can
+// see why a plugin moved.
+
+use std::{collections::{BTreeMap, HashMap}, fmt::{Display, Formatter}};
+
+use serde::Serialize;
+
+use crate::{
+  cli::ConflictPolicy,
+  error::AppError,
+  input::{
+    graph_edges,
+    EdgeFilter,
+    FlatPackage,
+    Input,
+    ResolvedPackage,
+    SatisfiedPackage,
+  },
+  jenkins_plugin_version::JenkinsPluginVersion,
+};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Conflict {
+  pub name: String,
+  pub resolved: JenkinsPluginVersion,
+  pub requirements: Vec<Requirement>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Requirement {
+  // "name version" of the dependent plugin, or "input" for our input file.
+  pub requester: String,
+  // Jenkins treats a dependency's version as a minimum.
+  pub minimum: JenkinsPluginVersion,
+  pub won: bool,
+}
+
+impl Display for Conflict {
+
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "{} {}", self.name, self.resolved)?;
+    for requirement in &self.requirements {
+      writeln!(
+        f,
+        "  {} asks for {}{}",
+        requirement.requester,
+        requirement.minimum,
+        if requirement.won { " (won)" } else { "" },
+      )?;
+    }
+    Ok(())
+  }
+
+}
+
+pub fn conflicts(
+  graph: &[SatisfiedPackage],
+  filter: &EdgeFilter,
+  resolved: &BTreeMap<String, FlatPackage>,
+) -> Vec<Conflict> {
+  let mut requirements: HashMap<String, Vec<Requirement>> = HashMap::new();
+  for (dependent, dependency) in graph_edges(graph, filter) {
+    let requirement = Requirement {
+      requester: dependent
+        .map(|d| format!("{} {}", d.name, d.version))
+        .unwrap_or("input".to_string()),
+      minimum: dependency.requested.clone(),
+      won: resolved
+        .get(&dependency.name)
+        .is_some_and(|r| r.version == dependency.requested),
+    };
+    let existing = requirements.entry(dependency.name.clone()).or_default();
+    if !existing.contains(&requirement) {
+      existing.push(requirement);
+    }
+  }
+  let mut conflicts = requirements
+    .into_iter()
+    .filter(|(_, requirements)| {
+      requirements
+        .iter()
+        .any(|r| r.minimum != requirements[0].minimum)
+    })
+    .filter_map(|(name, mut requirements)| {
+      requirements.sort_by(|a, b| {
+        b.minimum.cmp(&a.minimum).then_with(|| a.requester.cmp(&b.requester))
+      });
+      Some(Conflict {
+        resolved: resolved.get(&name)?.version.clone(),
+        name,
+        requirements,
+      })
+    })
+    .collect::<Vec<Conflict>>();
+  conflicts.sort_by(|a, b| a.name.cmp(&b.name));
+  conflicts
+}
+
+// Enforce the conflict policy on a report.  ConflictPolicy::Highest has already
+// been applied by picking the highest version, so there's nothing to check.
+pub fn conflicts_check(
+  report: &[Conflict],
+  policy: ConflictPolicy,
+  inputs: &[ResolvedPackage],
+) -> Result<(), AppError> {
+  match policy {
+    ConflictPolicy::Highest => Ok(()),
+    ConflictPolicy::Fail => {
+      if report.is_empty() {
+        Ok(())
+      } else {
+        Err(AppError::VersionConflictError(
+          report.iter().map(|c| c.to_string()).collect(),
+        ))
+      }
+    },
+    ConflictPolicy::Pinned => {
+      let too_low = report
+        .iter()
+        .filter(|conflict| inputs.iter().any(|i| i.name == conflict.name))
+        .flat_map(|conflict| {
+          conflict
+            .requirements
+            .iter()
+            .filter(|r| r.minimum > conflict.resolved)
+            .map(|r| format!(
+              "{} is pinned to {} but {} needs {}",
+              conflict.name,
+              conflict.resolved,
+              r.requester,
+              r.minimum,
+            ))
+        })
+        .collect::<Vec<String>>();
+      if too_low.is_empty() {
+        Ok(())
+      } else {
+        Err(AppError::PinnedVersionTooLowError(too_low))
+      }
+    },
+  }
+}
+
+// Greedy resolution starts from the version our input picked, and dependents
+// can push it up from there.  ConflictPolicy::Highest is asking for exactly
+// that, but under the other policies an exact version or an upper bound in our
+// input is a promise the result has to keep.
+pub fn constraints_check(
+  report: &[Conflict],
+  policy: ConflictPolicy,
+  input: &Input,
+  resolved: &BTreeMap<String, FlatPackage>,
+) -> Result<(), AppError> {
+  if policy == ConflictPolicy::Highest {
+    return Ok(());
+  }
+  let violations = input
+    .plugins_hash
+    .iter()
+    .filter_map(|(name, package)| {
+      let version = &resolved.get(name)?.version;
+      if package.version.satisfied_by(version) {
+        return None;
+      }
+      let requesters = report
+        .iter()
+        .filter(|conflict| &conflict.name == name)
+        .flat_map(|conflict| conflict.requirements.iter())
+        .filter(|r| !package.version.satisfied_by(&r.minimum))
+        .map(|r| format!("{} needs {}", r.requester, r.minimum))
+        .collect::<Vec<String>>();
+      Some(format!(
+        "{} is constrained to '{}' by our input but resolved to {}{}",
+        name,
+        package.version,
+        version,
+        if requesters.is_empty() {
+          String::new()
+        } else {
+          format!(" because {}", requesters.join(", "))
+        },
+      ))
+    })
+    .collect::<Vec<String>>();
+  if violations.is_empty() {
+    Ok(())
+  } else {
+    Err(AppError::InputConstraintViolatedError(violations))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use crate::{
+    cli::OptionalDependencies,
+    input::{graph_flatten, satisfied},
+  };
+
+  use super::*;
+
+  fn v(s: &str) -> JenkinsPluginVersion {
+    JenkinsPluginVersion::parse(s).unwrap()
+  }
+
+  fn asked(package: SatisfiedPackage, requested: &str) -> SatisfiedPackage {
+    SatisfiedPackage { requested: v(requested), ..package }
+  }
+
+  // git 5.3.0 needs scm-api 690, and our input asks for scm-api 680.  The
+  // graph is walked with scm-api at `scm_api`.
+  fn graph(scm_api: &str) -> Vec<SatisfiedPackage> {
+    vec!(
+      satisfied("git", "5.3.0", vec!(
+        asked(satisfied("scm-api", scm_api, vec!()), "690"),
+        satisfied("structs", "325", vec!()),
+      )),
+      asked(satisfied("scm-api", scm_api, vec!()), "680"),
+    )
+  }
+
+  fn resolved(graph: &[SatisfiedPackage]) -> BTreeMap<String, FlatPackage> {
+    let mut resolved: BTreeMap<String, FlatPackage> = BTreeMap::new();
+    for package in graph_flatten(graph, OptionalDependencies::All) {
+      let newer = resolved
+        .get(&package.name)
+        .is_none_or(|r| r.version < package.version);
+      if newer {
+        resolved.insert(package.name.clone(), package);
+      }
+    }
+    resolved
+  }
+
+  fn report(graph: &[SatisfiedPackage]) -> Vec<Conflict> {
+    let filter = EdgeFilter::new(graph, OptionalDependencies::All);
+    conflicts(graph, &filter, &resolved(graph))
+  }
+
+  fn inputs() -> Vec<ResolvedPackage> {
+    vec!(
+      ResolvedPackage { name: "git".to_string(), version: v("5.3.0") },
+      ResolvedPackage { name: "scm-api".to_string(), version: v("680") },
+    )
+  }
+
+  // Our input with scm-api constrained to `constraint`.
+  fn input(constraint: &str) -> Input {
+    serde_yaml::from_str(&[
+      "plugins_hash:",
+      "  git: { version: 5.3.0 }",
+      &format!("  scm-api: {{ version: '{}' }}", constraint),
+    ].join("\n"))
+      .unwrap()
+  }
+
+  #[test]
+  fn report_lists_every_ask() {
+    let report = report(&graph("690"));
+    assert_eq!(report.len(), 1);
+    assert_eq!(report[0].to_string(), [
+      "scm-api 690",
+      "  git 5.3.0 asks for 690 (won)",
+      "  input asks for 680",
+      "",
+    ].join("\n"));
+  }
+
+  #[test]
+  fn policies() {
+    let highest = report(&graph("690"));
+    assert!(
+      conflicts_check(&highest, ConflictPolicy::Highest, &inputs()).is_ok(),
+    );
+    assert!(matches!(
+      conflicts_check(&highest, ConflictPolicy::Fail, &inputs()),
+      Err(AppError::VersionConflictError(_)),
+    ));
+    let pinned = report(&graph("680"));
+    assert_eq!(pinned[0].resolved, v("680"));
+    match conflicts_check(&pinned, ConflictPolicy::Pinned, &inputs()) {
+      Err(AppError::PinnedVersionTooLowError(messages)) => assert_eq!(
+        messages,
+        ["scm-api is pinned to 680 but git 5.3.0 needs 690"],
+      ),
+      other => panic!("{:?}", other),
+    }
+  }
+
+  #[test]
+  fn constraints() {
+    let graph = graph("690");
+    let report = report(&graph);
+    let resolved = resolved(&graph);
+    let check = |policy, version| {
+      constraints_check(&report, policy, &input(version), &resolved)
+    };
+    assert!(check(ConflictPolicy::Highest, "680").is_ok());
+    assert!(check(ConflictPolicy::Pinned, ">= 680").is_ok());
+    match check(ConflictPolicy::Pinned, "680") {
+      Err(AppError::InputConstraintViolatedError(messages)) => assert_eq!(
+        messages,
+        [
+          "scm-api is constrained to '680' by our input but resolved to 690 \
+           because git 5.3.0 needs 690",
+        ],
+      ),
+      other => panic!("{:?}", other),
+    }
+    assert!(check(ConflictPolicy::Fail, ">= 600, < 690").is_err());
+  }
+
+}
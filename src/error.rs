@@ -3,21 +3,45 @@ use strum_macros::Display;
 #[derive(Debug, Display)]
 pub enum AppError {
   CachedArchiveMissingWarning(),
+  ChecksumMismatchError(String, String, String, String),
+  ChecksumMissingError(String, String),
   CachedManifestReadWarning(std::io::Error),
   CachedManifestMissingWarning(),
-  CliParseError(clap::error::Error),
   InputFileOpenError(std::io::Error),
   InputFileDeserializeError(serde_yaml::Error),
-  FileDecodeError(std::string::FromUtf8Error, String, String),
+  InputConstraintViolatedError(Vec<String>),
+  DependencyCycleError(Vec<String>),
   FileReadError(String, String, String),
+  HttpClientBuildError(String),
+  JenkinsCoreIncompatibleError(Vec<String>),
+  JsonSerializationError(serde_json::Error),
   LoggingInitializationError(log::SetLoggerError),
+  ManifestDecodeError(std::string::FromUtf8Error),
+  ManifestLineParseError(String),
+  NoMirrorsError(),
+  NoSolutionError(Vec<String>),
+  OutputReadError(String, std::io::Error),
+  OutputStaleError(String),
+  OutputWriteError(String, std::io::Error),
   PackageGetCallError(String, String, String),
+  PackageGetFailedEarlierError(String, String, String),
   PackageGetReadError(String, String, String),
+  PackageGetStatusError(String, u16),
   PackageUnzipError(zip::result::ZipError, String, String),
   PackageManifestSeekError(zip::result::ZipError, String, String),
+  PinnedVersionTooLowError(Vec<String>),
   PluginArchiveWriteError(String, std::io::Error),
   PluginHashFileReadError(String, std::io::Error),
+  PluginNotResolvedError(String),
   RemotePluginDeserializeError(String),
+  ResolutionUnsettledError(usize),
+  SolverStepsExceededError(usize),
+  UpdateCenterDeserializeError(String, serde_json::Error),
+  UpdateCenterGetError(String, String),
+  UpdateCenterReadError(String, std::io::Error),
+  VersionConstraintParseError(String),
+  VersionConstraintUnsatisfiedError(String, String),
+  VersionConflictError(Vec<String>),
   VersionParseError(),
   YamlSerializationError(serde_yaml::Error),
 }
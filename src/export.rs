@@ -0,0 +1,138 @@
+// The resolved graph in formats other tools can read, so it can be drawn or
+// scripted against without going back to the manifests.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::Serialize;
+
+use crate::{
+  input::{
+    graph_edges,
+    EdgeFilter,
+    FlatPackage,
+    ResolvedPackage,
+    SatisfiedPackage,
+  },
+  jenkins_plugin_version::JenkinsPluginVersion,
+};
+
+// Bump this whenever a field of the JSON graph is renamed, removed, or changes
+// meaning.  Adding a field doesn't need a bump.
+pub const GRAPH_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct JsonGraph {
+  pub schema_version: u32,
+  pub nodes: Vec<JsonNode>,
+  pub edges: Vec<JsonEdge>,
+}
+
+// One per resolved plugin.
+#[derive(Debug, Serialize)]
+pub struct JsonNode {
+  pub name: String,
+  pub version: JenkinsPluginVersion,
+  pub digest_type: String,
+  pub digest_string: String,
+  // Whether the plugin is in our input, rather than only a dependency.
+  pub explicit: bool,
+  pub jenkins_version: Option<JenkinsPluginVersion>,
+}
+
+// One per dependency, between the names of two nodes.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct JsonEdge {
+  pub from: String,
+  pub to: String,
+  pub requested: JenkinsPluginVersion,
+  pub optional: bool,
+}
+
+// Graphviz DOT, with our inputs filled in and optional edges dashed.  Nodes and
+// edges are sorted so the output only changes when the graph does.
+pub fn graph_dot(graph: &[SatisfiedPackage], filter: &EdgeFilter) -> String {
+  let id = |package: &SatisfiedPackage| {
+    dot_quote(&format!("{}@{}", package.name, package.version))
+  };
+  let mut nodes = BTreeSet::new();
+  let mut edges = BTreeSet::new();
+  for (dependent, dependency) in graph_edges(graph, filter) {
+    let explicit = dependent.is_none();
+    nodes.insert((id(dependency), explicit));
+    if let Some(dependent) = dependent {
+      edges.insert((id(dependent), id(dependency), dependency.optional));
+    }
+  }
+  let mut lines = vec!(
+    "digraph plugins {".to_string(),
+    "  node [shape=box];".to_string(),
+  );
+  for (node, explicit) in &nodes {
+    // An input can also be somebody's dependency, so only print it once.
+    if !explicit && nodes.contains(&(node.clone(), true)) {
+      continue;
+    }
+    lines.push(if *explicit {
+      format!(
+        "  {} [label={}, style=filled, fillcolor=lightblue];",
+        node,
+        node,
+      )
+    } else {
+      format!("  {} [label={}];", node, node)
+    });
+  }
+  for (from, to, optional) in &edges {
+    lines.push(if *optional {
+      format!("  {} -> {} [style=dashed];", from, to)
+    } else {
+      format!("  {} -> {};", from, to)
+    });
+  }
+  lines.push("}".to_string());
+  lines.join("\n")
+}
+
+fn dot_quote(s: &str) -> String {
+  format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// The resolved plugins and the dependencies between them as JSON, sorted by
+// name so the output only changes when the graph does.
+pub fn graph_json(
+  graph: &[SatisfiedPackage],
+  filter: &EdgeFilter,
+  resolved: &BTreeMap<String, FlatPackage>,
+  inputs: &[ResolvedPackage],
+) -> JsonGraph {
+  let nodes = resolved
+    .values()
+    .map(|package| JsonNode {
+      name: package.name.clone(),
+      version: package.version.clone(),
+      digest_type: package.digest_type.clone(),
+      digest_string: package.digest_string.clone(),
+      explicit: inputs.iter().any(|i| i.name == package.name),
+      jenkins_version: package.jenkins_version.clone(),
+    })
+    .collect::<Vec<JsonNode>>();
+  let mut edges: Vec<JsonEdge> = vec!();
+  for (dependent, dependency) in graph_edges(graph, filter) {
+    let Some(dependent) = dependent else { continue };
+    let edge = JsonEdge {
+      from: dependent.name.clone(),
+      to: dependency.name.clone(),
+      requested: dependency.requested.clone(),
+      optional: dependency.optional,
+    };
+    if !edges.contains(&edge) {
+      edges.push(edge);
+    }
+  }
+  edges.sort_by(|a, b| a.from.cmp(&b.from).then_with(|| a.to.cmp(&b.to)));
+  JsonGraph {
+    schema_version: GRAPH_SCHEMA_VERSION,
+    nodes,
+    edges,
+  }
+}
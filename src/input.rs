@@ -1,48 +1,43 @@
-use crate::{cli::Cli, error::AppError, jenkins_plugin_version::JenkinsPluginVersion};
+use crate::{
+  cli::{ConflictPolicy, OptionalDependencies},
+  error::AppError,
+  jenkins_plugin_version::JenkinsPluginVersion,
+  manifest::PluginManifest,
+  mirror::{mirror_needs_group, mirror_url, DEFAULT_GROUP_ID},
+  plugin_versions::PluginVersionsSource,
+  update_center::Checksums,
+  version_constraint::VersionConstraint,
+};
 use bytes::Bytes;
 use log::*;
-use reqwest::blocking;
+use reqwest::{blocking, StatusCode};
 use sha2::{Digest, Sha256};
-use std::{collections::HashMap, fs::File, io::{Cursor, Read}};
+use std::{
+  collections::{BTreeMap, HashMap, HashSet},
+  fs::File,
+  io::{Cursor, Read},
+  ops::Deref,
+  sync::{
+    atomic::{AtomicUsize, Ordering as AtomicOrdering},
+    Arc,
+    Mutex,
+  },
+  time::Duration,
+};
 use serde::{Deserialize, Serialize};
-use cached::proc_macro::cached;
-use cached::SizedCache;
-use regex::Regex;
 use std::io::Write;
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct Input {
-  pub plugins_hash: HashMap<String, InputPackage>,
+  pub plugins_hash: BTreeMap<String, InputPackage>,
 }
 
 // The input package is what is desired from our input file or CLI arguments.
 #[derive(Clone, Debug, Deserialize)]
 pub struct InputPackage {
-  // TODO: Consider making this a constraint.
-  pub version: JenkinsPluginVersion,
+  pub version: VersionConstraint,
 }
 
-// #[derive(Clone, Debug, Deserialize)]
-// pub enum VersionConstraint {
-//   Exact(VersionConstraintExact),
-//   // Latest(VersionConstraintLatest),
-//   // Between(VersionConstraintBetween),
-// }
-
-// #[derive(Clone, Debug, Deserialize)]
-// pub struct VersionConstraintExact {
-//   pub version: String,
-// }
-
-// #[derive(Clone, Debug, Deserialize)]
-// pub struct VersionConstraintLatest { }
-
-// #[derive(Clone, Debug, Deserialize)]
-// pub struct VersionConstraintBetween {
-//   pub version_upper_bound: String,
-//   pub version_lower_bound: String,
-// }
-
 // A resolved package is a transient structure that shows us what we found, but
 // doesn't include its dependencies and thus is incomplete.
 pub struct ResolvedPackage {
@@ -50,15 +45,68 @@ pub struct ResolvedPackage {
   pub version: JenkinsPluginVersion,
 }
 
+// Everything the dependency walk needs that stays the same for the whole run.
+pub struct DependencyContext<'a> {
+  // The version picked for each plugin so far.  Every dependency on a plugin
+  // is walked at this version, whatever version the dependent asked for.
+  pub selected: Mutex<HashMap<String, JenkinsPluginVersion>>,
+  pub optional_dependencies: OptionalDependencies,
+  pub conflict_policy: ConflictPolicy,
+  pub cache_dir: String,
+  // URL templates to download plugins from, in the order to try them.
+  pub mirrors: Vec<String>,
+  pub plugin_versions: &'a PluginVersionsSource,
+  pub client: blocking::Client,
+  // How many times to retry a download that failed in a way that might not
+  // happen again.
+  pub retries: u32,
+  pub checksums: Option<Checksums>,
+  // Drop the edge that closes a dependency cycle instead of failing.
+  pub break_cycles: bool,
+  // Packages already satisfied during this run, by name and version.
+  pub memo: Mutex<HashMap<(String, String), Arc<SatisfiedNode>>>,
+  // Packages that couldn't be fetched during this run, by name and version,
+  // and why.  They aren't tried again.
+  pub failed: Mutex<HashMap<(String, String), String>>,
+}
+
 // A satisfied package is a package that has been completely resolved as well as
-// all of its dependents.
+// all of its dependents.  Everything but the edge from its dependent is shared,
+// so handing a package out of the memo again doesn't copy the tree below it.
 #[derive(Clone, Debug)]
 pub struct SatisfiedPackage {
+  // The version the dependent (or our input) asked for.  This can be lower than
+  // the version, since another dependent may need something newer.
+  pub requested: JenkinsPluginVersion,
+  // Whether the edge from the parent to this package is optional.  Always
+  // false for the packages from our input.
+  pub optional: bool,
+  pub node: Arc<SatisfiedNode>,
+}
+
+// The part of a satisfied package that's the same whoever depends on it.
+#[derive(Debug)]
+pub struct SatisfiedNode {
   pub name: String,
   pub version: JenkinsPluginVersion,
   pub dependencies: Vec<SatisfiedPackage>,
   pub digest_string: String,
   pub digest_type: String,
+  // Where the archive was downloaded from.
+  pub url: String,
+  pub manifest: PluginManifest,
+  // Whether an edge closing a dependency cycle was left out somewhere below,
+  // which only holds for the path this was reached by.
+  pub cut: bool,
+}
+
+impl Deref for SatisfiedPackage {
+  type Target = SatisfiedNode;
+
+  fn deref(&self) -> &SatisfiedNode {
+    &self.node
+  }
+
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -68,28 +116,243 @@ pub struct FlatPackage {
   pub digest_string: String,
   pub digest_type: String,
   pub pin: bool,
+  // The rest are only used for checks, and not part of the Hiera output.
+  #[serde(skip)]
+  pub requested: JenkinsPluginVersion,
+  #[serde(skip)]
+  pub jenkins_version: Option<JenkinsPluginVersion>,
+  #[serde(skip)]
+  pub url: String,
 }
 
 impl SatisfiedPackage {
 
-  pub fn flatten(&self) -> Vec<FlatPackage> {
-    let mut packages = self
-      .dependencies
-      .clone()
-      .into_iter()
-      .map(|d| d.flatten())
-      .flatten()
-      .collect::<Vec<FlatPackage>>();
+  // Flatten the tree, only descending into dependencies that `follow` accepts.
+  // There's a package for every edge, since each can ask for a different
+  // version, but a package shared by several dependents is only descended into
+  // the first time.  `expanded` holds the packages descended into so far.
+  pub fn flatten<F>(
+    &self,
+    follow: &F,
+    expanded: &mut HashSet<*const SatisfiedNode>,
+    packages: &mut Vec<FlatPackage>,
+  )
+    where F: Fn(&SatisfiedPackage) -> bool,
+  {
+    if expanded.insert(Arc::as_ptr(&self.node)) {
+      for dependency in self.dependencies.iter().filter(|d| follow(d)) {
+        dependency.flatten(follow, expanded, packages);
+      }
+    }
     packages.push(FlatPackage {
       name: self.name.clone(),
       version: self.version.clone(),
       digest_string: self.digest_string.clone(),
       digest_type: self.digest_type.clone(),
       pin: true,
+      requested: self.requested.clone(),
+      jenkins_version: self.manifest.jenkins_version.clone(),
+      url: self.url.clone(),
     });
+  }
+
+  // The first path from this package down to the named package at the given
+  // version, as "name version" labels, or None if it isn't in this tree.
+  pub fn chain_to(
+    &self,
+    name: &str,
+    version: &JenkinsPluginVersion,
+  ) -> Option<Vec<String>> {
+    // A package already searched below doesn't lead there from anywhere else
+    // either.
+    fn search(
+      package: &SatisfiedPackage,
+      name: &str,
+      version: &JenkinsPluginVersion,
+      searched: &mut HashSet<*const SatisfiedNode>,
+    ) -> Option<Vec<String>> {
+      let label = format!("{} {}", package.name, package.version);
+      if package.name == name && &package.version == version {
+        return Some(vec!(label));
+      }
+      if !searched.insert(Arc::as_ptr(&package.node)) {
+        return None;
+      }
+      package
+        .dependencies
+        .iter()
+        .find_map(|d| search(d, name, version, searched))
+        .map(|chain| std::iter::once(label).chain(chain).collect())
+    }
+    search(self, name, version, &mut HashSet::new())
+  }
+
+}
+
+// A package as the walk would have satisfied it, for tests of what reads the
+// graph.  It was asked for at the version it resolved to.
+#[cfg(test)]
+pub fn satisfied(
+  name: &str,
+  version: &str,
+  dependencies: Vec<SatisfiedPackage>,
+) -> SatisfiedPackage {
+  let version = JenkinsPluginVersion::parse(version).unwrap();
+  SatisfiedPackage {
+    requested: version.clone(),
+    optional: false,
+    node: Arc::new(SatisfiedNode {
+      name: name.to_string(),
+      version,
+      dependencies,
+      digest_string: String::new(),
+      digest_type: "sha256".to_string(),
+      url: String::new(),
+      manifest: PluginManifest::default(),
+      cut: false,
+    }),
+  }
+}
+
+// Flatten the whole graph according to how optional dependencies are treated.
+// For OptionalDependencies::IfRequired an optional edge is followed once its
+// plugin is pulled in by something else.  Following it can pull in more
+// plugins, which can make more optional edges count, so keep going until the
+// set of plugins stops growing.
+pub fn graph_flatten(
+  graph: &[SatisfiedPackage],
+  optional_dependencies: OptionalDependencies,
+) -> Vec<FlatPackage> {
+  let flatten_following = |follow: &dyn Fn(&SatisfiedPackage) -> bool| {
+    let mut expanded = HashSet::new();
+    let mut packages = vec!();
+    for package in graph {
+      package.flatten(&follow, &mut expanded, &mut packages);
+    }
     packages
+  };
+  match optional_dependencies {
+    OptionalDependencies::All => flatten_following(&|_| true),
+    OptionalDependencies::Exclude => flatten_following(&|d| !d.optional),
+    OptionalDependencies::IfRequired => {
+      let names = |packages: &[FlatPackage]| {
+        packages
+          .iter()
+          .map(|p| p.name.clone())
+          .collect::<HashSet<String>>()
+      };
+      let mut packages = flatten_following(&|d| !d.optional);
+      loop {
+        let required = names(&packages);
+        let next = flatten_following(&|d| {
+          !d.optional || required.contains(&d.name)
+        });
+        if names(&next).len() == required.len() {
+          return next;
+        }
+        packages = next;
+      }
+    },
   }
+}
+
+// Decides which dependency edges of the graph count, the same way
+// graph_flatten does.
+pub struct EdgeFilter {
+  optional_dependencies: OptionalDependencies,
+  required: HashSet<String>,
+}
+
+impl EdgeFilter {
+
+  pub fn new(
+    graph: &[SatisfiedPackage],
+    optional_dependencies: OptionalDependencies,
+  ) -> Self {
+    EdgeFilter {
+      optional_dependencies,
+      required: graph_flatten(graph, optional_dependencies)
+        .into_iter()
+        .map(|p| p.name)
+        .collect(),
+    }
+  }
+
+  pub fn follows(&self, dependency: &SatisfiedPackage) -> bool {
+    match self.optional_dependencies {
+      OptionalDependencies::All => true,
+      OptionalDependencies::Exclude => !dependency.optional,
+      OptionalDependencies::IfRequired => {
+        !dependency.optional || self.required.contains(&dependency.name)
+      },
+    }
+  }
+
+}
+
+// Every edge of the graph that the filter follows, as (dependent, dependency)
+// pairs.  Our inputs have no dependent.  A plugin shared by several dependents
+// only has its own edges listed once, though the same edge can still come up
+// again in a dependent built under a different path (see --break-cycles).
+pub fn graph_edges<'a>(
+  graph: &'a [SatisfiedPackage],
+  filter: &EdgeFilter,
+) -> Vec<(Option<&'a SatisfiedPackage>, &'a SatisfiedPackage)> {
+  fn visit<'a>(
+    package: &'a SatisfiedPackage,
+    filter: &EdgeFilter,
+    expanded: &mut HashSet<*const SatisfiedNode>,
+    edges: &mut Vec<(Option<&'a SatisfiedPackage>, &'a SatisfiedPackage)>,
+  ) {
+    if !expanded.insert(Arc::as_ptr(&package.node)) {
+      return;
+    }
+    for dependency in package.dependencies.iter().filter(|d| filter.follows(d)) {
+      edges.push((Some(package), dependency));
+      visit(dependency, filter, expanded, edges);
+    }
+  }
+  let mut expanded = HashSet::new();
+  let mut edges = vec!();
+  for root in graph {
+    edges.push((None, root));
+    visit(root, filter, &mut expanded, &mut edges);
+  }
+  edges
+}
 
+// Turn each input constraint into a concrete version.  The published version
+// listing is only loaded if some constraint is not an exact pin, so inputs made
+// entirely of exact versions keep working offline.
+pub fn input_resolve(
+  input: &Input,
+  plugin_versions: &PluginVersionsSource,
+) -> Result<Vec<ResolvedPackage>, AppError> {
+  input
+    .plugins_hash
+    .iter()
+    .map(|(name, package)| {
+      let version = match &package.version {
+        VersionConstraint::Exact(version) => version.clone(),
+        constraint => {
+          let candidates = plugin_versions.get()?.versions(name);
+          let version = constraint
+            .select(&candidates)
+            .ok_or_else(|| AppError::VersionConstraintUnsatisfiedError(
+              name.clone(),
+              constraint.to_string(),
+            ))
+            ?;
+          info!("Selected {} {} for constraint '{}'.", name, version, constraint);
+          version
+        },
+      };
+      Ok(ResolvedPackage {
+        name: name.clone(),
+        version,
+      })
+    })
+    .collect()
 }
 
 fn archive_cache_path(
@@ -100,12 +363,49 @@ fn archive_cache_path(
   format!("{}/{}--{}.hpi", cache_dir, name, version)
 }
 
+fn manifest_cache_path(
+  cache_dir: &String,
+  name: &String,
+  version: &JenkinsPluginVersion,
+) -> String {
+  format!("{}/{}--{}.mf", cache_dir, name, version)
+}
+
+fn url_cache_path(
+  cache_dir: &String,
+  name: &String,
+  version: &JenkinsPluginVersion,
+) -> String {
+  format!("{}/{}--{}.url", cache_dir, name, version)
+}
+
+// The URL the archive was downloaded from.  Archives cached before we kept
+// track of that are assumed to have come from the first mirror.
+fn archive_url(
+  context: &DependencyContext,
+  name: &String,
+  version: &JenkinsPluginVersion,
+) -> String {
+  let path = url_cache_path(&context.cache_dir, name, version);
+  match std::fs::read_to_string(&path) {
+    Ok(url) => url.trim().to_string(),
+    Err(_) => {
+      debug!("No {}, assuming the first mirror.", path);
+      context
+        .mirrors
+        .first()
+        .map(|template| dependency_url(context, template, name, version))
+        .unwrap_or_default()
+    },
+  }
+}
+
 fn archive_hash_file(
   cache_dir: &String,
   name: &String,
   version: &JenkinsPluginVersion,
 ) -> Result<(String, String), AppError> {
-  let archive_path = archive_cache_path(&cache_dir, &name, &version);
+  let archive_path = archive_cache_path(cache_dir, name, version);
   let mut file = File::open(&archive_path)
     .map_err(|e| AppError::PluginHashFileReadError(archive_path.clone(), e) )
     ?;
@@ -120,7 +420,7 @@ fn archive_hash_bytes(
   buffer: &Bytes,
 ) -> Result<(String, String), AppError> {
   let mut hasher = Sha256::new();
-  hasher.update(&buffer);
+  hasher.update(buffer);
   let digest = hasher.finalize();
   Ok((format!("{:x}", digest), "sha256".to_string()))
 }
@@ -131,12 +431,12 @@ fn archive_write(
   version: &JenkinsPluginVersion,
   bytes: &Bytes,
 ) -> Result<(), AppError> {
-  let archive_path = archive_cache_path(&&cache_dir, name, &version);
+  let archive_path = archive_cache_path(cache_dir, name, version);
   let mut file = File::create(&archive_path)
     .map_err(|e| {
       AppError::PluginArchiveWriteError(archive_path.clone(), e)
     })?;
-  file.write_all(&bytes)
+  file.write_all(bytes)
     .map_err(|e| {
       AppError::PluginArchiveWriteError(archive_path.clone(), e)
     })?;
@@ -144,115 +444,222 @@ fn archive_write(
   Ok(())
 }
 
+// Remove an archive from the cache, along with the manifest and URL kept
+// beside it.
+fn archive_evict(
+  cache_dir: &String,
+  name: &String,
+  version: &JenkinsPluginVersion,
+) {
+  for path in [
+    archive_cache_path(cache_dir, name, version),
+    manifest_cache_path(cache_dir, name, version),
+    url_cache_path(cache_dir, name, version),
+  ] {
+    match std::fs::remove_file(&path) {
+      Ok(_) => info!("Removed {} from the cache.", path),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+      Err(e) => warn!("Could not remove {}.  Error: {}", path, e),
+    }
+  }
+}
+
 // TODO: Ugh I did all of this and only later found there's a DiskCache in
 // cached.  Take a look!
 pub fn dependency_http(
-  cache_dir: String,
+  context: &DependencyContext,
   name: String,
   version: JenkinsPluginVersion,
-) -> Result<String, AppError> {
-  let url = format!(
-    "https://get.jenkins.io/plugins/{}/{}/{}.hpi",
-    name,
-    version,
-    name,
-  );
-  info!("Trying url: {}", url);
-  let response = blocking::get(url)
-    .map_err(|e| AppError::PackageGetCallError(
-      e.to_string(),
-      name.clone(),
-      version.to_string(),
-    ))
-    ?;
-  debug!("Response for {}: {}", name, response.status());
-  let bytes = response.bytes()
-    .map_err(|e| AppError::PackageGetReadError(
-      e.to_string(),
-      name.clone(),
-      version.to_string(),
-    ))
-    ?;
-  // Bytes::clone doesn't actually make a copy but clones a reference.  You want
-  // to_vec for strict copies, unintuitively.
-  archive_write(&cache_dir, &name, &version, &bytes.clone())?;
-  let mut archive = zip::ZipArchive::new(Cursor::new(bytes))
+) -> Result<PluginManifest, AppError> {
+  let mut last_error = AppError::NoMirrorsError();
+  for template in &context.mirrors {
+    match dependency_mirror(context, template, &name, &version) {
+      Ok(manifest) => return Ok(manifest),
+      Err(e) => {
+        warn!(
+          "Could not get {} {} from mirror '{}'.  Error: {:?}",
+          name,
+          version,
+          template,
+          e,
+        );
+        last_error = e;
+      },
+    }
+  }
+  Err(last_error)
+}
+
+fn dependency_group_id(
+  context: &DependencyContext,
+  name: &str,
+  version: &JenkinsPluginVersion,
+) -> String {
+  match context.plugin_versions.get() {
+    Ok(listing) => listing
+      .group_id(name, version)
+      .unwrap_or_else(|| DEFAULT_GROUP_ID.to_string()),
+    Err(e) => {
+      warn!(
+        "Could not look up the group of {}, assuming {}.  Error: {:?}",
+        name,
+        DEFAULT_GROUP_ID,
+        e,
+      );
+      DEFAULT_GROUP_ID.to_string()
+    },
+  }
+}
+
+fn dependency_url(
+  context: &DependencyContext,
+  template: &str,
+  name: &str,
+  version: &JenkinsPluginVersion,
+) -> String {
+  let group_id = if mirror_needs_group(template) {
+    dependency_group_id(context, name, version)
+  } else {
+    DEFAULT_GROUP_ID.to_string()
+  };
+  mirror_url(template, name, version, &group_id)
+}
+
+fn dependency_mirror(
+  context: &DependencyContext,
+  template: &str,
+  name: &String,
+  version: &JenkinsPluginVersion,
+) -> Result<PluginManifest, AppError> {
+  let cache_dir = &context.cache_dir;
+  let url = dependency_url(context, template, name, version);
+  let bytes = archive_download(context, &url, name, version)?;
+  // Check against the published checksums before anything is cached, so a
+  // corrupt or tampered download never lands where the next run would trust
+  // it.  A mismatch moves on to the next mirror.
+  if let Some(checksums) = &context.checksums {
+    let (digest_string, _) = archive_hash_bytes(&bytes)?;
+    checksums.verify(name, version, &digest_string)?;
+  }
+  // Only a zip with a manifest we can read goes into the cache.  Anything else
+  // would be found there on the next run and fail in confusing ways.
+  let mut archive = zip::ZipArchive::new(Cursor::new(bytes.clone()))
     .map_err(|e| AppError::PackageUnzipError(
       e,
       name.clone(),
       version.to_string(),
     ))
     ?;
+  let mut buffer = Vec::new();
   archive
     .by_name("META-INF/MANIFEST.MF")
     .map_err(|e| AppError::PackageManifestSeekError(e, name.clone(), version.to_string()))
-    // .map(|zip_file| zip_file.bytes())
-    .and_then(|mut zipped_file| {
-      let mut buffer = Vec::new();
-      zipped_file.read_to_end(&mut buffer)
-        .map_err(|e| {
-          AppError::FileReadError(
-            e.to_string(),
-            name.clone(),
-            version.to_string(),
-          )
-        })
-        ?;
-      String::from_utf8(buffer)
-        .map_err(|e| {
-          AppError::FileDecodeError(e, name.clone(), version.to_string())
-        })
-        .map(|manifest| {
-          // Fun fact: MANIFEST.MF files can wrap lines with values.  To do
-          // this, prefix the next line with a single space.  There might be
-          // more to it, but this is observed only.  We don't care about
-          // preserving the formatting, so just strip the space and the prior
-          // line ending.
-          manifest
-            // Unknown if these are optional.  Just strip them.  Not doing so
-            // consistently fouls up the next replace.
-            .replace("\r", "")
-            .replace("\n ", "")
-          // manifest
-        })
-        .inspect(|manifest| debug!("Manifest for {}:\n{}", name, manifest) )
-        .inspect(|manifest| {
-          let path = format!("{}/{}--{}.mf", cache_dir, name, version);
-          let file_res = File::create(&path);
-          match file_res {
-            Ok(mut file) => {
-              let write_res = write!(file, "{}", manifest);
-              match write_res {
-                Ok(_) => (),
-                Err(e) => warn!(
-                  "Error writing {}.  Non-panic error due to caching nature.  Error: {}",
-                  path,
-                  e,
-                )
-              }
-            },
-            Err(e) => warn!(
-              "Error writing {}.  Non-panic error due to caching nature.  Error: {}",
-              path,
-              e,
-            )
-          }
-        })
+    ?
+    .read_to_end(&mut buffer)
+    .map_err(|e| {
+      AppError::FileReadError(
+        e.to_string(),
+        name.clone(),
+        version.to_string(),
+      )
     })
+    ?;
+  debug!(
+    "Manifest for {}:\n{}",
+    name,
+    String::from_utf8_lossy(&buffer),
+  );
+  let manifest = PluginManifest::parse(&buffer)?;
+  archive_write(cache_dir, name, version, &bytes)?;
+  // Cache the manifest as it was written.  Parsing it again is cheap compared
+  // to unzipping the archive.  The URL is kept for outputs that download the
+  // archive again themselves.
+  for (path, contents) in [
+    (manifest_cache_path(cache_dir, name, version), buffer.as_slice()),
+    (url_cache_path(cache_dir, name, version), url.as_bytes()),
+  ] {
+    match File::create(&path).and_then(|mut file| file.write_all(contents)) {
+      Ok(_) => (),
+      Err(e) => warn!(
+        "Error writing {}.  Non-panic error due to caching nature.  Error: {}",
+        path,
+        e,
+      ),
+    };
+  }
+  Ok(manifest)
+}
+
+// Download an archive, retrying with exponential backoff when the failure
+// looks transient: timeouts, server errors, and rate limiting.  Anything else
+// (such as a 404) fails straight away so the next mirror can be tried.
+fn archive_download(
+  context: &DependencyContext,
+  url: &str,
+  name: &String,
+  version: &JenkinsPluginVersion,
+) -> Result<Bytes, AppError> {
+  let mut attempt = 0;
+  loop {
+    info!("Trying url: {}", url);
+    let result = context
+      .client
+      .get(url)
+      .send()
+      .map_err(|e| (
+        e.is_timeout(),
+        AppError::PackageGetCallError(
+          e.to_string(),
+          name.clone(),
+          version.to_string(),
+        ),
+      ))
+      .and_then(|response| {
+        let status = response.status();
+        debug!("Response for {}: {}", name, status);
+        if status.is_success() {
+          response.bytes().map_err(|e| (
+            e.is_timeout(),
+            AppError::PackageGetReadError(
+              e.to_string(),
+              name.clone(),
+              version.to_string(),
+            ),
+          ))
+        } else {
+          Err((
+            status.is_server_error()
+              || status == StatusCode::TOO_MANY_REQUESTS,
+            AppError::PackageGetStatusError(url.to_string(), status.as_u16()),
+          ))
+        }
+      });
+    match result {
+      Ok(bytes) => return Ok(bytes),
+      Err((true, e)) if attempt < context.retries => {
+        let delay = Duration::from_millis(500 * 2_u64.pow(attempt));
+        warn!("Retrying {} in {:?}.  Error: {:?}", url, delay, e);
+        std::thread::sleep(delay);
+        attempt += 1;
+      },
+      Err((_, e)) => return Err(e),
+    }
+  }
 }
 
 pub fn cached_manifest(
   cache_dir: String,
   name: String,
   version: JenkinsPluginVersion,
-) -> Result<String, AppError> {
-  let manifest_path = format!("{}/{}--{}.mf", cache_dir, name, version);
+) -> Result<PluginManifest, AppError> {
+  let manifest_path = manifest_cache_path(&cache_dir, &name, &version);
   let archive_path = archive_cache_path(&cache_dir, &name, &version);
   if std::fs::exists(&manifest_path).unwrap() {
     if std::fs::exists(&archive_path).unwrap() {
-      std::fs::read_to_string(&manifest_path)
+      std::fs::read(&manifest_path)
         .inspect(|_| info!("Found {} in cache.", manifest_path))
         .map_err(AppError::CachedManifestReadWarning)
+        .and_then(|bytes| PluginManifest::parse(&bytes))
     } else {
       warn!("Manifest is present, but {} archive is missing.", archive_path);
       Err(AppError::CachedArchiveMissingWarning())
@@ -262,123 +669,432 @@ pub fn cached_manifest(
   }
 }
 
-// Actually since I have manual disk caching implemented, I can just disable
-// this.  This should be disabled until I can figure out how to handle fancy
-// arguments and borrows.
-// #[cached(
-//   ty = "SizedCache<String, usize>",
-//   create = "{ SizedCache::with_size(100) }",
-//   convert = r#"{ format!("{}-{}", name, version) }"#,
-//   result = true,
-// )]
+// The manifest of a package, from the cache or else a mirror.  A package that
+// failed once fails the same way for the rest of the run, rather than going
+// through every mirror and retry again.
+fn dependency_fetch(
+  context: &DependencyContext,
+  name: &str,
+  version: &JenkinsPluginVersion,
+) -> Result<PluginManifest, AppError> {
+  let key = (name.to_string(), version.to_string());
+  let failed = context
+    .failed
+    .lock()
+    .unwrap_or_else(|e| e.into_inner())
+    .get(&key)
+    .cloned();
+  if let Some(reason) = failed {
+    return Err(AppError::PackageGetFailedEarlierError(
+      name.to_string(),
+      version.to_string(),
+      reason,
+    ));
+  }
+  cached_manifest(context.cache_dir.clone(), name.to_string(), version.clone())
+    .or_else(|_| dependency_http(context, name.to_string(), version.clone()))
+    .inspect_err(|e| {
+      context
+        .failed
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(key, format!("{:?}", e));
+    })
+}
+
+fn selected_version(
+  context: &DependencyContext,
+  name: &str,
+  requested: &JenkinsPluginVersion,
+) -> JenkinsPluginVersion {
+  context
+    .selected
+    .lock()
+    .unwrap_or_else(|e| e.into_inner())
+    .get(name)
+    .cloned()
+    .unwrap_or(requested.clone())
+}
+
+// Resolution can't be done in a single walk.  Once the highest requested
+// version of a plugin is picked, that version's own dependencies can differ
+// from those of the version its dependent asked for.  So walk the graph, pick
+// the highest requested version of everything in it, and walk it again at
+// those versions until the picks stop changing.
+//
+// A pick only ever goes up while its plugin is still in the graph, which keeps
+// this from flip-flopping between two answers.  With ConflictPolicy::Pinned the
+// versions from our input never move at all.
+//
+// The seed is a starting pick for anything beyond our input, such as the
+// solver's answer.  A consistent seed settles after a single walk.
+pub fn graph_resolve(
+  context: &DependencyContext,
+  roots: &[ResolvedPackage],
+  seed: &HashMap<String, JenkinsPluginVersion>,
+  jobs: usize,
+) -> Result<Vec<SatisfiedPackage>, AppError> {
+  let mut selected = seed.clone();
+  selected.extend(roots.iter().map(|p| (p.name.clone(), p.version.clone())));
+  for iteration in 1..=RESOLVE_ITERATIONS_MAX {
+    *context.selected.lock().unwrap_or_else(|e| e.into_inner()) =
+      selected.clone();
+    context.memo.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    dependency_prefetch(context, roots, jobs)?;
+    let graph = roots
+      .iter()
+      .map(|package| {
+        dependency(context, package.name.clone(), &package.version, false, &[])
+      })
+      .collect::<Result<Vec<SatisfiedPackage>, AppError>>()
+      ?;
+    let mut next: HashMap<String, JenkinsPluginVersion> = HashMap::new();
+    if context.conflict_policy == ConflictPolicy::Pinned {
+      for root in roots {
+        next.insert(root.name.clone(), root.version.clone());
+      }
+    }
+    for package in graph_flatten(&graph, context.optional_dependencies) {
+      if context.conflict_policy == ConflictPolicy::Pinned
+        && roots.iter().any(|root| root.name == package.name) {
+        continue;
+      }
+      let floor = selected
+        .get(&package.name)
+        .filter(|previous| **previous > package.requested)
+        .unwrap_or(&package.requested)
+        .clone();
+      let version = next.entry(package.name).or_insert(floor.clone());
+      if floor > *version {
+        *version = floor;
+      }
+    }
+    if next == selected {
+      debug!("Resolution settled after {} walk(s).", iteration);
+      return Ok(graph);
+    }
+    info!("Selected versions changed, walking the graph again.");
+    selected = next;
+  }
+  Err(AppError::ResolutionUnsettledError(RESOLVE_ITERATIONS_MAX))
+}
+
+const RESOLVE_ITERATIONS_MAX: usize = 100;
+
+// Shared plugins (such as structs) are depended upon by dozens of others, so
+// each name and version is only satisfied once per run and then handed out of
+// context.memo.  This used to be a #[cached] attempt, but the disk cache and
+// borrowed arguments didn't get along with it.
+//
+// `ancestors` is the path from one of our inputs down to (but not including)
+// this package, which is how we notice cycles.
 pub fn dependency(
-  specified: &Vec<ResolvedPackage>,
-  cache_dir: String,
+  context: &DependencyContext,
   name: String,
   version: &JenkinsPluginVersion,
+  optional: bool,
+  ancestors: &[(String, JenkinsPluginVersion)],
 ) -> Result<SatisfiedPackage, AppError> {
-  let real_version = specified
-    .into_iter()
-    .find(|p| p.name == name)
-    .map(|p| p.version.clone())
-    .unwrap_or(version.clone());
-  // This is said to "move" the variable, but I don't see its effect.
-  let _ = version;
-  let dependencies = cached_manifest(
-    cache_dir.clone(),
-    name.clone(),
-    real_version.clone(),
-  )
-    .or_else(|_| { dependency_http(
-      cache_dir.clone(),
-      name.clone(),
-      real_version.clone(),
-    ) })
-    .and_then(parse_dependencies)
-    .and_then(|deps| {
-      deps
-        .into_iter()
-        .map(|dep| {
-          dependency(&specified, cache_dir.clone(), dep.name, &dep.version)
-        })
-        .collect()
-    })?;
+  let real_version = selected_version(context, &name, version);
+  let key = (name.clone(), real_version.to_string());
+  let memoized = context
+    .memo
+    .lock()
+    .unwrap_or_else(|e| e.into_inner())
+    .get(&key)
+    .cloned();
+  if let Some(node) = memoized {
+    // The same package can be optional for one dependent and required by
+    // another, so that part belongs to the edge and not the memo.
+    return Ok(SatisfiedPackage {
+      requested: version.clone(),
+      optional,
+      node,
+    });
+  }
+  let manifest = dependency_fetch(context, &name, &real_version)?;
+  let mut path = ancestors.to_vec();
+  path.push((name.clone(), real_version.clone()));
+  let mut cut = false;
+  let dependencies = manifest
+    .plugin_dependencies
+    .iter()
+    .filter(|dep| {
+      // There's no point in fetching what we will throw away.
+      !dep.optional
+        || context.optional_dependencies != OptionalDependencies::Exclude
+    })
+    .filter_map(|dep| {
+      let result = match path
+        .iter()
+        .position(|(ancestor, _)| *ancestor == dep.name) {
+        Some(start) => {
+          let cycle = path[start..]
+            .iter()
+            .map(|(n, v)| format!("{} {}", n, v))
+            .chain(std::iter::once(format!("{} {}", dep.name, dep.version)))
+            .collect::<Vec<String>>();
+          if context.break_cycles {
+            warn!("Breaking dependency cycle: {}", cycle.join(" -> "));
+            cut = true;
+            None
+          } else {
+            Some(Err(AppError::DependencyCycleError(cycle)))
+          }
+        },
+        None => Some(dependency(
+          context,
+          dep.name.clone(),
+          &dep.version,
+          dep.optional,
+          &path,
+        )),
+      };
+      match result {
+        // An optional dependency that nothing requires gets dropped from the
+        // output anyway, so whatever went wrong with it shouldn't stop the
+        // run.  If something does require it, that edge fails instead.
+        Some(Err(e)) if optional_tolerated(context, dep.optional) => {
+          warn!(
+            "Leaving out optional dependency {} of {} {}.  Error: {:?}",
+            dep.name,
+            name,
+            real_version,
+            e,
+          );
+          cut |= matches!(e, AppError::DependencyCycleError(_));
+          None
+        },
+        Some(Ok(dependency)) => {
+          cut |= dependency.cut;
+          Some(Ok(dependency))
+        },
+        result => result,
+      }
+    })
+    .collect::<Result<Vec<SatisfiedPackage>, AppError>>()?;
   let (digest_string, digest_type) = archive_hash_file(
-    &cache_dir,
+    &context.cache_dir,
     &name,
     &real_version,
   )?;
-  Ok(SatisfiedPackage {
+  // Downloads are checked before they're cached, so this only catches archives
+  // cached before we had checksums, or changed since.  One that doesn't match
+  // is removed so the next run downloads it again.
+  if let Some(checksums) = &context.checksums {
+    match checksums.verify(&name, &real_version, &digest_string) {
+      Err(e @ AppError::ChecksumMismatchError(..)) => {
+        archive_evict(&context.cache_dir, &name, &real_version);
+        return Err(e);
+      },
+      result => result?,
+    }
+  }
+  let url = archive_url(context, &name, &real_version);
+  let node = Arc::new(SatisfiedNode {
     name,
     version: real_version,
     dependencies,
     digest_string,
     digest_type,
+    url,
+    manifest,
+    cut,
+  });
+  // What a cut package depends on depends on how it was reached, so it isn't
+  // handed out to anything else.
+  if !cut {
+    context
+      .memo
+      .lock()
+      .unwrap_or_else(|e| e.into_inner())
+      .insert(key, node.clone());
+  }
+  Ok(SatisfiedPackage {
+    requested: version.clone(),
+    optional,
+    node,
   })
 }
 
-fn _dependency_latest(name: String) {
-  let _ = format!("https://updates.jenkins-ci.org/latest/{}.hpi", name);
+// Make sure every manifest and archive in the graph is in the cache, fetching
+// up to `jobs` of them at a time.  The graph is walked breadth first, and
+// each name and version is only fetched once no matter how many plugins depend
+// on it.  This doesn't build the graph itself; that's left to `dependency`,
+// which then only ever reads from the cache and so stays deterministic.
+//
+// Failures are only fatal for packages reached without an optional edge along
+// the way, the same as in the walk.
+pub fn dependency_prefetch(
+  context: &DependencyContext,
+  roots: &[ResolvedPackage],
+  jobs: usize,
+) -> Result<(), AppError> {
+  let mut seen: HashSet<(String, String)> = HashSet::new();
+  let mut frontier = roots
+    .iter()
+    .map(|p| {
+      (p.name.clone(), selected_version(context, &p.name, &p.version), false)
+    })
+    .collect::<Vec<(String, JenkinsPluginVersion, bool)>>();
+  while !frontier.is_empty() {
+    frontier.retain(|(name, version, _)| {
+      seen.insert((name.clone(), version.to_string()))
+    });
+    let packages = frontier
+      .iter()
+      .map(|(name, version, _)| (name.clone(), version.clone()))
+      .collect::<Vec<(String, JenkinsPluginVersion)>>();
+    let mut next = vec!();
+    for ((name, version, optional), result) in
+      frontier.iter().zip(prefetch_level(context, &packages, jobs)) {
+      let manifest = match result {
+        Ok(manifest) => manifest,
+        Err(e) if optional_tolerated(context, *optional) => {
+          warn!(
+            "Could not prefetch optional {} {}.  Error: {:?}",
+            name,
+            version,
+            e,
+          );
+          continue;
+        },
+        Err(e) => return Err(e),
+      };
+      next.extend(
+        manifest
+          .plugin_dependencies
+          .iter()
+          .filter(|dep| {
+            !dep.optional
+              || context.optional_dependencies != OptionalDependencies::Exclude
+          })
+          .map(|dep| (
+            dep.name.clone(),
+            selected_version(context, &dep.name, &dep.version),
+            *optional || dep.optional,
+          ))
+          .filter(|(name, version, _)| {
+            !seen.contains(&(name.clone(), version.to_string()))
+          })
+      );
+    }
+    frontier = next;
+  }
+  Ok(())
+}
+
+// Whether a failure behind an optional edge can be shrugged off.  With
+// OptionalDependencies::All optional dependencies are as good as required.
+fn optional_tolerated(context: &DependencyContext, optional: bool) -> bool {
+  optional
+    && context.optional_dependencies == OptionalDependencies::IfRequired
 }
 
-/**
- * The MANIFEST.MF file is a line-break separated file, wit keys, colons, and
- * values.  You can see the format here:
- * https://wiki.jenkins.io/display/JENKINS/Plugin+Structure
- * We need to look for all lines that start with "Plugin-Dependencies: ", and
- * read the comma separated list of <name>:<version>.  It is not known if the
- * version can be a range.  There might be multiple Plugin-Dependencies lines.
- */
-fn parse_dependencies(manifest: String) -> Result<Vec<ResolvedPackage>, AppError> {
-  manifest
-    .split("\n")
+// Fetch one level of the graph with a pool of workers.  Results are kept in the
+// order they were asked for, so the first error reported is always the same
+// one.
+fn prefetch_level(
+  context: &DependencyContext,
+  packages: &[(String, JenkinsPluginVersion)],
+  jobs: usize,
+) -> Vec<Result<PluginManifest, AppError>> {
+  let next = AtomicUsize::new(0);
+  let results: Mutex<Vec<Option<Result<PluginManifest, AppError>>>> =
+    Mutex::new(packages.iter().map(|_| None).collect());
+  std::thread::scope(|scope| {
+    for _ in 0..jobs.clamp(1, packages.len().max(1)) {
+      scope.spawn(|| loop {
+        let i = next.fetch_add(1, AtomicOrdering::SeqCst);
+        let Some((name, version)) = packages.get(i) else { break };
+        let result = dependency_fetch(context, name, version);
+        results.lock().unwrap_or_else(|e| e.into_inner())[i] = Some(result);
+      });
+    }
+  });
+  results
+    .into_inner()
+    .unwrap_or_else(|e| e.into_inner())
     .into_iter()
-    .filter(|line| line.starts_with("Plugin-Dependencies:") )
-    .map(|line| {
-      let matches = Regex::new(r"Plugin-Dependencies: ?(.*)$")
-        .unwrap()
-        .captures(line);
-      match matches {
-        Some(m) => {
-          match m.get(1) {
-            Some(s) => s
-              .as_str()
-              .split(",")
-              .map(from_name_version_string)
-              .collect(),
-            None => vec!(),
-          }
-        },
-        None => vec!(),
-      }
-    })
     .flatten()
     .collect()
 }
 
-fn from_name_version_string(plugin_pair: &str) -> Result<ResolvedPackage, AppError> {
-  let [ name, version_and_resolution ] = TryInto::<[String; 2]>::try_into(
-    plugin_pair
-      .split_once(":")
+fn _dependency_latest(name: String) {
+  let _ = format!("https://updates.jenkins-ci.org/latest/{}.hpi", name);
+}
+
+#[cfg(test)]
+mod tests {
+
+  use std::collections::BTreeSet;
+
+  use super::*;
+
+  fn optional(package: SatisfiedPackage) -> SatisfiedPackage {
+    SatisfiedPackage { optional: true, ..package }
+  }
+
+  fn names(packages: &[FlatPackage]) -> Vec<String> {
+    packages
+      .iter()
+      .map(|p| p.name.clone())
+      .collect::<BTreeSet<String>>()
       .into_iter()
-      .collect::<(String, String)>()
-  )
-    .map_err(|_| AppError::RemotePluginDeserializeError(plugin_pair.into()))
-    ?;
-  // Versions can be split further into a resolution type.  We don't care about
-  // that right now.
-  let rust_weakness = version_and_resolution
-    .split(";")
-    .collect::<Vec<&str>>()
-    ;
-  // I don't know how this unwrap could fail.  I don't feel like fighting you
-  // today, Rust.
-  let version = rust_weakness
-    .get(0)
-    .unwrap()
-    ;
-  Ok(ResolvedPackage {
-    name: name.to_string(),
-    version: JenkinsPluginVersion::parse(&version.to_string())?,
-  })
+      .collect()
+  }
+
+  // Two plugins on each of `levels` levels, each depending on both plugins of
+  // the level below, for 2^levels paths through 2 * levels plugins.
+  fn diamonds(levels: usize) -> Vec<SatisfiedPackage> {
+    let mut below: Vec<SatisfiedPackage> = vec!();
+    for level in (0..levels).rev() {
+      below = ["l", "r"]
+        .iter()
+        .map(|side| {
+          satisfied(&format!("{}{}", side, level), "1.0", below.clone())
+        })
+        .collect();
+    }
+    below
+  }
+
+  #[test]
+  fn flatten_follows_optional_edges_if_required() {
+    let graph = vec!(
+      satisfied("a", "1.0", vec!(
+        optional(satisfied("b", "1.0", vec!(satisfied("c", "1.0", vec!())))),
+        optional(satisfied("d", "1.0", vec!())),
+      )),
+      satisfied("e", "1.0", vec!(satisfied("b", "1.0", vec!()))),
+    );
+    assert_eq!(
+      names(&graph_flatten(&graph, OptionalDependencies::Exclude)),
+      ["a", "b", "e"],
+    );
+    assert_eq!(
+      names(&graph_flatten(&graph, OptionalDependencies::IfRequired)),
+      ["a", "b", "c", "e"],
+    );
+    assert_eq!(
+      names(&graph_flatten(&graph, OptionalDependencies::All)),
+      ["a", "b", "c", "d", "e"],
+    );
+  }
+
+  #[test]
+  fn shared_plugins_are_walked_once() {
+    let graph = diamonds(30);
+    let packages = graph_flatten(&graph, OptionalDependencies::IfRequired);
+    assert_eq!(names(&packages).len(), 60);
+    // Two roots, and four edges out of every level but the last.
+    assert_eq!(packages.len(), 2 + 4 * 29);
+    let filter = EdgeFilter::new(&graph, OptionalDependencies::IfRequired);
+    assert_eq!(graph_edges(&graph, &filter).len(), 2 + 4 * 29);
+    assert_eq!(
+      graph[0].chain_to("z", &JenkinsPluginVersion::parse("1.0").unwrap()),
+      None,
+    );
+  }
+
 }
@@ -0,0 +1,95 @@
+// Every plugin declares the oldest Jenkins core it runs on with the
+// Jenkins-Version header of its manifest.  Installing a plugin on an older core
+// leaves it disabled, along with everything that depends on it, so we check the
+// resolved set against the core we intend to run before anyone rolls it out.
+
+use std::{collections::BTreeMap, fmt::{Display, Formatter}};
+
+use serde::Serialize;
+
+use crate::{
+  input::{FlatPackage, SatisfiedPackage},
+  jenkins_plugin_version::JenkinsPluginVersion,
+};
+
+#[derive(Clone, Debug)]
+pub struct CoreIncompatibility {
+  pub name: String,
+  pub version: JenkinsPluginVersion,
+  pub jenkins_version: JenkinsPluginVersion,
+  pub target: JenkinsPluginVersion,
+  // How the plugin ended up in the resolved set, from one of our inputs down
+  // to the plugin itself.
+  pub chain: Vec<String>,
+}
+
+impl Display for CoreIncompatibility {
+
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    write!(
+      f,
+      "{} {} requires Jenkins {} but the target is {}.  Pulled in by: {}",
+      self.name,
+      self.version,
+      self.jenkins_version,
+      self.target,
+      self.chain.join(" -> "),
+    )
+  }
+
+}
+
+pub fn core_incompatibilities(
+  graph: &[SatisfiedPackage],
+  resolved: &BTreeMap<String, FlatPackage>,
+  target: &JenkinsPluginVersion,
+) -> Vec<CoreIncompatibility> {
+  let mut incompatibilities = resolved
+    .values()
+    .filter_map(|package| {
+      package
+        .jenkins_version
+        .as_ref()
+        .filter(|required| *required > target)
+        .map(|required| CoreIncompatibility {
+          name: package.name.clone(),
+          version: package.version.clone(),
+          jenkins_version: required.clone(),
+          target: target.clone(),
+          chain: graph
+            .iter()
+            .find_map(|p| p.chain_to(&package.name, &package.version))
+            .unwrap_or_default(),
+        })
+    })
+    .collect::<Vec<CoreIncompatibility>>();
+  incompatibilities.sort_by(|a, b| a.name.cmp(&b.name));
+  incompatibilities
+}
+
+// The oldest core that can run every resolved plugin, and the plugins that
+// require it.  Anything else could move to an older core on its own.
+#[derive(Clone, Debug, Serialize)]
+pub struct MinimumCore {
+  pub version: JenkinsPluginVersion,
+  pub required_by: Vec<String>,
+}
+
+pub fn minimum_core(
+  resolved: &BTreeMap<String, FlatPackage>,
+) -> Option<MinimumCore> {
+  let version = resolved
+    .values()
+    .filter_map(|package| package.jenkins_version.clone())
+    .max()?;
+  let mut required_by = resolved
+    .values()
+    .filter(|package| package.jenkins_version.as_ref() == Some(&version))
+    .map(|package| package.name.clone())
+    .collect::<Vec<String>>();
+  required_by.sort();
+  Some(MinimumCore {
+    version,
+    required_by,
+  })
+}
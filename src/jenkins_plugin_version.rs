@@ -1,7 +1,19 @@
 // We could've used the semver, if Jenkins Plugins used semver...
-// They just use... whatever?  Let's just assume it's dotted segments, and we'll
-// do numeric comparisons.  That may not always be safe because some patch
-// versions are like "a" and "e", but whatever.
+// They don't, but they are Maven artifacts, so their versions follow Maven's
+// ComparableVersion rules.  This is a port of those rules:
+//
+// - Versions are split into items on "." and "-", and on any change between
+//   digits and letters.  "-" (and a digit/letter change) starts a nested list,
+//   so "1-1" sorts before "1.1".
+// - Numeric items compare as numbers, and always beat qualifiers.
+// - Qualifiers order as alpha < beta < milestone < rc < snapshot < "" < sp, and
+//   anything unknown sorts after those, alphabetically.  "ga", "final" and
+//   "release" are the same as "", "cr" is "rc", and "a1", "b1", "m1" are
+//   shorthand for alpha, beta and milestone.
+// - Trailing zeros and empty qualifiers are trimmed, so "1.0" is "1" and
+//   "1.0-ga" is "1", but "1.2" is still less than "1.2.1".
+//
+// See https://maven.apache.org/ref/3.9.9/maven-artifact/apidocs/org/apache/maven/artifact/versioning/ComparableVersion.html
 
 use std::{cmp::Ordering, fmt::{Display, Formatter}};
 use log::*;
@@ -10,58 +22,201 @@ use serde::{de::{self, Visitor}, Deserialize, Deserializer, Serialize, Serialize
 
 use crate::error::AppError;
 
-#[derive(Debug, Clone, Eq, PartialOrd, PartialEq)]
+const QUALIFIERS: [&str; 7] =
+  ["alpha", "beta", "milestone", "rc", "snapshot", "", "sp"];
+
+#[derive(Debug, Clone)]
 pub struct JenkinsPluginVersion {
-  pub segments: Vec<String>,
+  // The version as written, which is what we display and serialize.
+  original: String,
+  items: Vec<VersionItem>,
+}
+
+#[derive(Debug, Clone)]
+enum VersionItem {
+  // Digits with the leading zeros stripped.  Kept as a string so arbitrarily
+  // long numbers (such as timestamps) compare without overflowing.
+  Int(String),
+  Str(String),
+  List(Vec<VersionItem>),
+}
+
+impl VersionItem {
+
+  fn int(digits: &str) -> Self {
+    VersionItem::Int(digits.trim_start_matches('0').to_string())
+  }
+
+  fn qualifier(value: &str, followed_by_digit: bool) -> Self {
+    let value = match value {
+      "a" if followed_by_digit => "alpha",
+      "b" if followed_by_digit => "beta",
+      "m" if followed_by_digit => "milestone",
+      "ga" | "final" | "release" => "",
+      "cr" => "rc",
+      other => other,
+    };
+    VersionItem::Str(value.to_string())
+  }
+
+  // Qualifiers are ranked by their position in QUALIFIERS, and unknown ones
+  // come after all of the known ones in alphabetical order.
+  fn comparable_qualifier(value: &str) -> String {
+    match QUALIFIERS.iter().position(|q| *q == value) {
+      Some(index) => index.to_string(),
+      None => format!("{}-{}", QUALIFIERS.len(), value),
+    }
+  }
+
+  fn is_null(&self) -> bool {
+    match self {
+      VersionItem::Int(digits) => digits.is_empty(),
+      VersionItem::Str(value) => value.is_empty(),
+      VersionItem::List(items) => items.is_empty(),
+    }
+  }
+
+  // Compare against another item, where None stands in for the padding used
+  // when one version has fewer items than the other.
+  fn compare(&self, other: Option<&VersionItem>) -> Ordering {
+    use VersionItem::*;
+    match (self, other) {
+      (Int(digits), None) => {
+        if digits.is_empty() { Ordering::Equal } else { Ordering::Greater }
+      },
+      (Int(a), Some(Int(b))) => a.len().cmp(&b.len()).then_with(|| a.cmp(b)),
+      (Int(_), Some(_)) => Ordering::Greater,
+      (Str(value), None) => Self::comparable_qualifier(value)
+        .cmp(&Self::comparable_qualifier("")),
+      (Str(_), Some(Int(_))) => Ordering::Less,
+      (Str(a), Some(Str(b))) => Self::comparable_qualifier(a)
+        .cmp(&Self::comparable_qualifier(b)),
+      (Str(_), Some(List(_))) => Ordering::Less,
+      (List(items), None) => items
+        .first()
+        .map(|first| first.compare(None))
+        .unwrap_or(Ordering::Equal),
+      (List(_), Some(Int(_))) => Ordering::Less,
+      (List(_), Some(Str(_))) => Ordering::Greater,
+      (List(a), Some(List(b))) => Self::compare_lists(a, b),
+    }
+  }
+
+  fn compare_lists(a: &[VersionItem], b: &[VersionItem]) -> Ordering {
+    (0..a.len().max(b.len()))
+      .map(|i| match (a.get(i), b.get(i)) {
+        (Some(left), right) => left.compare(right),
+        (None, Some(right)) => right.compare(None).reverse(),
+        (None, None) => Ordering::Equal,
+      })
+      .find(|ordering| *ordering != Ordering::Equal)
+      .unwrap_or(Ordering::Equal)
+  }
+
+  // Strip null items from the end of a list.  Nested lists don't stop the
+  // trimming, so the zero in "1.0-beta" is removed as well.
+  fn normalize(items: &mut Vec<VersionItem>) {
+    let mut i = items.len();
+    while i > 0 {
+      i -= 1;
+      if items[i].is_null() {
+        items.remove(i);
+      } else if !matches!(items[i], VersionItem::List(_)) {
+        break;
+      }
+    }
+  }
+
 }
 
 impl JenkinsPluginVersion {
 
-  pub fn parse(s: &String) -> Result<Self, AppError> {
-    let segments = s
-      .split(".")
-      .into_iter()
-      .map(|s| s.to_string())
-      .collect::<Vec<String>>();
-    // TODO: Maybe validate more?
-    if Self::segments_valid(&segments) {
-      Ok(
-        JenkinsPluginVersion {
-          segments,
-        }
-      )
-    } else {
-      Err(AppError::VersionParseError())
+  pub fn parse(s: &str) -> Result<Self, AppError> {
+    if s.trim().is_empty() {
+      return Err(AppError::VersionParseError());
     }
+    Ok(JenkinsPluginVersion {
+      original: s.to_string(),
+      items: Self::parse_items(&s.to_lowercase()),
+    })
   }
 
-  fn segments_valid(segments: &Vec<String>) -> bool {
-    segments.len() != 0
-  }
-
-  fn numeric_segments(&self) -> Vec<u64> {
-    self.segments
-      .iter()
-      .map(|segment| {
-        match segment.parse::<u64>() {
-          Ok(num) => num,
-          Err(_) => {
-            let (total, _) = segment
-              .chars()
-              .into_iter()
-              .fold((0 as u64, 0 as u64), |(acc, power), num | {
-                (
-                  acc + (
-                    num as u64 * (8 as u64).pow(power.try_into().unwrap())
-                  ),
-                  power + 1,
-                )
-              });
-            total
-          }
+  // Maven builds a chain of lists, where each "-" (or change between digits
+  // and letters) appends a new list to the end of the current one and carries
+  // on inside it.  We keep that chain as a stack and fold it back together at
+  // the end, normalizing from the innermost list outward.
+  fn parse_items(version: &str) -> Vec<VersionItem> {
+    let chars = version.chars().collect::<Vec<char>>();
+    let mut stack: Vec<Vec<VersionItem>> = vec!(vec!());
+    let mut is_digit = false;
+    let mut start = 0;
+    let item = |is_digit: bool, from: usize, to: usize| {
+      let buffer = chars[from..to].iter().collect::<String>();
+      if is_digit {
+        VersionItem::int(&buffer)
+      } else {
+        VersionItem::qualifier(&buffer, false)
+      }
+    };
+    for (i, c) in chars.iter().enumerate() {
+      let current = stack.len() - 1;
+      if *c == '.' || *c == '-' {
+        if i == start {
+          stack[current].push(VersionItem::Int(String::new()));
+        } else {
+          stack[current].push(item(is_digit, start, i));
         }
-      })
-      .collect::<Vec<u64>>()
+        start = i + 1;
+        if *c == '-' {
+          stack.push(vec!());
+        }
+      } else if c.is_ascii_digit() {
+        if !is_digit && i > start {
+          let buffer = chars[start..i].iter().collect::<String>();
+          stack[current].push(VersionItem::qualifier(&buffer, true));
+          start = i;
+          stack.push(vec!());
+        }
+        is_digit = true;
+      } else {
+        if is_digit && i > start {
+          stack[current].push(item(true, start, i));
+          start = i;
+          stack.push(vec!());
+        }
+        is_digit = false;
+      }
+    }
+    if chars.len() > start {
+      let current = stack.len() - 1;
+      stack[current].push(item(is_digit, start, chars.len()));
+    }
+    let mut items = stack.pop().unwrap_or_default();
+    VersionItem::normalize(&mut items);
+    while let Some(mut parent) = stack.pop() {
+      parent.push(VersionItem::List(items));
+      VersionItem::normalize(&mut parent);
+      items = parent;
+    }
+    items
+  }
+
+}
+
+impl PartialEq for JenkinsPluginVersion {
+
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == Ordering::Equal
+  }
+
+}
+
+impl Eq for JenkinsPluginVersion {}
+
+impl PartialOrd for JenkinsPluginVersion {
+
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
   }
 
 }
@@ -69,25 +224,15 @@ impl JenkinsPluginVersion {
 impl Ord for JenkinsPluginVersion {
 
   fn cmp(&self, other: &Self) -> Ordering {
-    self
-      .numeric_segments()
-      .into_iter()
-      .zip(other.numeric_segments())
-      .fold(Ordering::Equal, |acc, (a, b)| {
-        // Essentially, continue until we hit a non-equal segment.
-        if acc != Ordering::Equal {
-          acc
-        } else {
-          a.cmp(&b)
-        }
-      })
+    VersionItem::compare_lists(&self.items, &other.items)
   }
+
 }
 
 impl Display for JenkinsPluginVersion {
 
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-    f.write_str(&self.segments.join("."))
+    f.write_str(&self.original)
   }
 
 }
@@ -107,25 +252,18 @@ impl<'de> Visitor<'de> for JenkinsPluginVersionVisitor {
   type Value = JenkinsPluginVersion;
 
   fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
-    formatter.write_str("a string with dotted segments")
+    formatter.write_str("a Maven style version string")
   }
 
   fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
   where E: de::Error,
   {
     // TODO: Provide error information in the parse, and then map it along here.
-    JenkinsPluginVersion::parse(&value.to_string())
+    JenkinsPluginVersion::parse(value)
       .map_err(|e| {
         error!("Somehow this is validating incorrect: {} {}", value, e);
         E::custom(format!("invalid value for JenkinsPluginVersion: {}", value))
       })
-    // Ok(Self::Value {
-    //   segments: value
-    //     .split(".")
-    //     .into_iter()
-    //     .map(|s| s.to_string())
-    //     .collect::<Vec<String>>()
-    // })
   }
 
   fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
@@ -141,7 +279,102 @@ impl Serialize for JenkinsPluginVersion {
   fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where S: Serializer,
   {
-    serializer.serialize_str(&self.segments.join("."))
+    serializer.serialize_str(&self.original)
+  }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn v(s: &str) -> JenkinsPluginVersion {
+    JenkinsPluginVersion::parse(s).unwrap()
+  }
+
+  // Each version must sort strictly before the next.
+  fn assert_ascending(versions: &[&str]) {
+    for pair in versions.windows(2) {
+      assert!(v(pair[0]) < v(pair[1]), "{} < {}", pair[0], pair[1]);
+      assert!(v(pair[1]) > v(pair[0]), "{} > {}", pair[1], pair[0]);
+    }
+  }
+
+  fn assert_equal(versions: &[&str]) {
+    for a in versions {
+      for b in versions {
+        assert_eq!(v(a), v(b), "{} == {}", a, b);
+      }
+    }
+  }
+
+  #[test]
+  fn numbers() {
+    assert_ascending(&["1", "1.1"]);
+    assert_ascending(&["1.2", "1.2.1"]);
+    assert_ascending(&["1.9", "1.10", "2"]);
+    assert_ascending(&["1-1", "1.1"]);
+  }
+
+  #[test]
+  fn qualifiers() {
+    assert_ascending(&[
+      "1-alpha",
+      "1-beta",
+      "1-milestone",
+      "1-rc",
+      "1-snapshot",
+      "1",
+      "1-sp",
+      "1-unknown",
+    ]);
+  }
+
+  #[test]
+  fn release_aliases() {
+    assert_equal(&["1.0", "1", "1.0-ga", "1-final", "1-release", "1.0.0"]);
+  }
+
+  #[test]
+  fn shorthand_qualifiers() {
+    assert_equal(&["1-a1", "1-alpha-1"]);
+    assert_equal(&["1-b1", "1-beta-1"]);
+    assert_equal(&["1-m1", "1-milestone-1"]);
+    assert_equal(&["1-cr", "1-rc"]);
+    assert_ascending(&["1-a1", "1-b1", "1-m1", "1-rc1", "1"]);
+  }
+
+  #[test]
+  fn timestamps() {
+    assert_ascending(&[
+      "1.0-20240101123456",
+      "1.0-20240101123457",
+      "1.0-120240101123456",
+    ]);
+    assert_equal(&["1.0-020240101123456", "1.0-20240101123456"]);
+  }
+
+  #[test]
+  fn jenkins_versions() {
+    assert_ascending(&[
+      "2.5.2",
+      "2.6-rc.1",
+      "2.6",
+      "325.v1",
+      "1337.v60b_d7b_c7b_c9f",
+      "1400.v7fd111b_ec82f",
+    ]);
+  }
+
+  #[test]
+  fn display_keeps_original() {
+    assert_eq!(v("1.0-GA").to_string(), "1.0-GA");
+  }
+
+  #[test]
+  fn empty() {
+    assert!(JenkinsPluginVersion::parse(" ").is_err());
   }
 
 }
@@ -1,32 +1,76 @@
 mod cli;
+mod conflicts;
 mod error;
+mod export;
 mod input;
+mod jenkins_core;
 mod logger;
+mod manifest;
+mod output;
+mod mirror;
 mod jenkins_plugin_version;
+mod plugin_versions;
+mod solver;
+mod tree;
+mod update_center;
+mod version_constraint;
+mod why;
 
-use std::{cmp::Ordering, collections::HashMap, hash::Hash, io::BufReader};
+use std::{
+  cmp::Ordering,
+  collections::{BTreeMap, HashMap},
+  io::{BufReader, IsTerminal},
+  sync::Mutex,
+};
 
 use clap::Parser;
-use cli::Cli;
+use cli::{Cli, Command, GraphFormat, Solver};
+use conflicts::{conflicts, conflicts_check, constraints_check};
 use error::AppError;
-use input::{dependency, FlatPackage, Input, ResolvedPackage, SatisfiedPackage};
+use export::{graph_dot, graph_json};
+use jenkins_core::{core_incompatibilities, minimum_core};
+use jenkins_plugin_version::JenkinsPluginVersion;
+use input::{
+  graph_flatten,
+  graph_resolve,
+  EdgeFilter,
+  DependencyContext,
+  input_resolve,
+  Input,
+  ResolvedPackage,
+};
 use itertools::Itertools;
 use log::*;
 use logger::logger_init;
+use output::{
+  output_check,
+  output_write,
+  output_writer,
+  OutputOptions,
+  Resolution,
+};
+use plugin_versions::PluginVersionsSource;
+use solver::solve;
+use tree::{tree, tree_inverted, TreeOptions};
+use update_center::checksums_load;
 use serde::Serialize;
+use version_constraint::VersionConstraint;
+use why::why;
 
 #[derive(Serialize)]
 pub struct JenkinsPuppetHashVersion {
   pub version: String,
 }
 
+// Maps are ordered by key so that everything printed from them comes out in the
+// same order every run.
 fn group_by<Key, Value, F: Fn(&Value) -> Key>(
   grouping: F,
   xs: Vec<Value>,
-) -> HashMap<Key, Vec<Value>>
-  where Key: Eq, Key: Hash
+) -> BTreeMap<Key, Vec<Value>>
+  where Key: Ord
 {
-  let mut map: HashMap<Key, Vec<Value>> = HashMap::new();
+  let mut map: BTreeMap<Key, Vec<Value>> = BTreeMap::new();
   for item in xs {
     let key = grouping(&item);
     map.entry(key).or_default().push(item);
@@ -36,14 +80,14 @@ fn group_by<Key, Value, F: Fn(&Value) -> Key>(
 
 fn resolve<Key, Value, Sort: Fn(&Value, &Value) -> Ordering>(
   sort: Sort,
-  grouped: HashMap<Key, Vec<Value>>,
-) -> HashMap<Key, Value> where Key: Eq, Key: Hash {
-  let mut map: HashMap<Key, Value> = HashMap::new();
+  grouped: BTreeMap<Key, Vec<Value>>,
+) -> BTreeMap<Key, Value> where Key: Ord {
+  let mut map: BTreeMap<Key, Value> = BTreeMap::new();
   for (key, values) in grouped {
     let val = values
       .into_iter()
       .sorted_by(&sort)
-      .nth(0)
+      .next()
       ;
     match val {
       Some(value) => { map.insert(key, value); },
@@ -84,40 +128,82 @@ fn main() -> Result<(), AppError> {
   )
     .map_err(AppError::InputFileDeserializeError)
     ?;
-  let specified_dependencies = input
-    .plugins_hash
-    .iter()
-    .map(|(name, package)| {
-      ResolvedPackage {
-        name: name.to_string(),
-        version: package.version.clone(),
-      }
-    })
-    .collect();
+  let plugin_versions = PluginVersionsSource::new(
+    &cache_dir,
+    &cli.plugin_versions,
+  );
+  let jenkins_version = cli
+    .jenkins_version
+    .as_ref()
+    .map(|v| JenkinsPluginVersion::parse(v))
+    .transpose()
+    ?;
+  let (specified_dependencies, seed) = match cli.solver {
+    Solver::Greedy => {
+      (input_resolve(&input, &plugin_versions)?, HashMap::new())
+    },
+    Solver::Backtracking => {
+      let constraints = input
+        .plugins_hash
+        .iter()
+        .map(|(name, package)| (name.clone(), package.version.clone()))
+        .collect::<BTreeMap<String, VersionConstraint>>();
+      let solution = solve(
+        plugin_versions.get()?,
+        &constraints,
+        cli.optional_dependencies,
+        jenkins_version.as_ref(),
+      )
+        .inspect_err(|e| {
+          if let AppError::NoSolutionError(explanations) = e {
+            for explanation in explanations {
+              error!("{}", explanation);
+            }
+          }
+        })
+        ?;
+      let roots = constraints
+        .keys()
+        .map(|name| ResolvedPackage {
+          name: name.clone(),
+          version: solution[name].clone(),
+        })
+        .collect::<Vec<ResolvedPackage>>();
+      (roots, solution)
+    },
+  };
+  let context = DependencyContext {
+    selected: Mutex::new(HashMap::new()),
+    optional_dependencies: cli.optional_dependencies,
+    conflict_policy: cli.conflict_policy,
+    cache_dir: cache_dir.clone(),
+    mirrors: cli.mirror.clone(),
+    plugin_versions: &plugin_versions,
+    client: reqwest::blocking::Client::builder()
+      .build()
+      .map_err(|e| AppError::HttpClientBuildError(e.to_string()))
+      ?,
+    retries: cli.retries,
+    checksums: cli
+      .checksums
+      .as_ref()
+      .map(|location| checksums_load(&cache_dir, location))
+      .transpose()
+      ?,
+    break_cycles: cli.break_cycles,
+    memo: Mutex::new(HashMap::new()),
+    failed: Mutex::new(HashMap::new()),
+  };
   // Take the inputs and request them.
   // Then take the dependencies from that list and request those.
   // Keep going until there are no more unsatisfied dependencies.
-  let graph = input
-    .plugins_hash
-    .into_iter()
-    .map(|(name, package)| {
-      // match package.version_constraint {
-      //   VersionConstraint::Exact(v) => dependency(name, v.version),
-      // }
-      dependency(
-        &specified_dependencies,
-        cache_dir.clone(),
-        name,
-        &package.version,
-      )
-    })
-    .collect::<Result<Vec<SatisfiedPackage>, AppError>>()
-    ?;
-  let packages = graph
-    .into_iter()
-    .map(|p| p.flatten())
-    .flatten()
-    .collect::<Vec<FlatPackage>>()
+  let graph = graph_resolve(
+    &context,
+    &specified_dependencies,
+    &seed,
+    cli.jobs,
+  )?;
+  let packages = graph_flatten(&graph, cli.optional_dependencies)
     // .map(|p| {
     //   (p.name, JenkinsPuppetHashVersion { version: p.version, })
     // })
@@ -134,11 +220,123 @@ fn main() -> Result<(), AppError> {
     grouped,
   );
 
-  let mut output_helper = HashMap::new();
-  output_helper.insert("jenkins::plugin_hash", &resolved);
-  let yaml = serde_yaml::to_string(&output_helper)
-    .map_err(AppError::YamlSerializationError)
-    ?;
-  println!("{}", yaml);
+  let filter = EdgeFilter::new(&graph, cli.optional_dependencies);
+  let report = conflicts(&graph, &filter, &resolved);
+  // The conflicts report, why and tree are how to find out why the policy
+  // failed, so they shouldn't be stopped by the policy.
+  if !matches!(
+    cli.command,
+    Some(
+      Command::Conflicts { .. } | Command::Why { .. } | Command::Tree { .. }
+    ),
+  ) {
+    conflicts_check(&report, cli.conflict_policy, &specified_dependencies)?;
+    constraints_check(&report, cli.conflict_policy, &input, &resolved)?;
+  }
+
+  if let Some(target) = &jenkins_version {
+    let incompatibilities = core_incompatibilities(&graph, &resolved, target);
+    for incompatibility in &incompatibilities {
+      if cli.allow_incompatible_core {
+        warn!("{}", incompatibility);
+      } else {
+        error!("{}", incompatibility);
+      }
+    }
+    if !incompatibilities.is_empty() && !cli.allow_incompatible_core {
+      return Err(AppError::JenkinsCoreIncompatibleError(
+        incompatibilities
+          .iter()
+          .map(|i| i.to_string())
+          .collect(),
+      ));
+    }
+  }
+
+  // Everything printed from here on can go to --output instead, so build it
+  // up first.
+  let text = match cli.command {
+    Some(Command::MinimumCore) => {
+      match minimum_core(&resolved) {
+        Some(minimum) => format!(
+          "{} (required by {})\n",
+          minimum.version,
+          minimum
+            .required_by
+            .iter()
+            .map(|name| format!("{} {}", name, resolved[name].version))
+            .collect::<Vec<String>>()
+            .join(", "),
+        ),
+        None => {
+          warn!("No resolved plugin declares a Jenkins-Version.");
+          String::new()
+        },
+      }
+    },
+    Some(Command::Conflicts { json }) => {
+      if json {
+        serde_json::to_string_pretty(&report)
+          .map_err(AppError::JsonSerializationError)
+          ?
+          + "\n"
+      } else {
+        report.iter().map(|conflict| conflict.to_string()).collect()
+      }
+    },
+    Some(Command::Why { plugin }) => {
+      why(&graph, &filter, &plugin)?
+        .iter()
+        .map(|chain| format!("{}\n", chain))
+        .collect()
+    },
+    Some(Command::Tree { invert, depth }) => {
+      let options = TreeOptions {
+        depth,
+        color: cli.output.is_none()
+          && std::io::stdout().is_terminal()
+          && std::env::var_os("NO_COLOR").is_none(),
+      };
+      match invert {
+        Some(name) => tree_inverted(&graph, &filter, &name, &options)? + "\n",
+        None => tree(&graph, &filter, &options) + "\n",
+      }
+    },
+    Some(Command::Graph { format }) => {
+      match format {
+        GraphFormat::Dot => graph_dot(&graph, &filter) + "\n",
+        GraphFormat::Json => {
+          serde_json::to_string_pretty(&graph_json(
+            &graph,
+            &filter,
+            &resolved,
+            &specified_dependencies,
+          ))
+            .map_err(AppError::JsonSerializationError)
+            ?
+            + "\n"
+        },
+      }
+    },
+    None => {
+      let resolution = Resolution {
+        resolved: &resolved,
+        minimum_core: if cli.emit_minimum_core {
+          minimum_core(&resolved)
+        } else {
+          None
+        },
+      };
+      let options = OutputOptions {
+        helm_disable_latest: cli.helm_disable_latest,
+      };
+      output_writer(cli.format, &options).write(&resolution)?
+    },
+  };
+  match &cli.output {
+    Some(path) if cli.check => output_check(path, &text)?,
+    Some(path) => output_write(path, &text)?,
+    None => print!("{}", text),
+  }
   Ok(())
 }
@@ -0,0 +1,311 @@
+// The META-INF/MANIFEST.MF of a plugin archive follows the JAR manifest format:
+// https://docs.oracle.com/en/java/javase/21/docs/specs/jar/jar.html#jar-manifest
+//
+// - Lines end in CR LF, LF, or CR.
+// - Lines are at most 72 bytes, and longer values are wrapped by starting the
+//   next line with a single space.  The wrapping is done on bytes, so it can
+//   split a multi-byte UTF-8 character in two.  That's why we join lines before
+//   decoding anything.
+// - Headers are "Name: value".
+// - The main section runs up to the first blank line.  Any sections after that
+//   describe individual entries in the archive, which we don't care about.
+//
+// Jenkins keeps its plugin metadata in the main section.  The headers are
+// described here: https://wiki.jenkins.io/display/JENKINS/Plugin+Structure
+
+use std::collections::BTreeMap;
+
+use crate::{error::AppError, jenkins_plugin_version::JenkinsPluginVersion};
+
+// Not everything here is used yet.  It's parsed anyway so nothing needs to go
+// back to the manifest text for it later.
+#[allow(dead_code)]
+#[derive(Clone, Debug, Default)]
+pub struct PluginManifest {
+  pub short_name: Option<String>,
+  pub long_name: Option<String>,
+  // Not a JenkinsPluginVersion since locally built plugins put things like
+  // "1.0-SNAPSHOT (private-jdoe)" in here.
+  pub plugin_version: Option<String>,
+  pub jenkins_version: Option<JenkinsPluginVersion>,
+  pub minimum_java_version: Option<String>,
+  pub compatible_since_version: Option<JenkinsPluginVersion>,
+  pub url: Option<String>,
+  pub plugin_developers: Vec<PluginDeveloper>,
+  pub plugin_dependencies: Vec<PluginDependency>,
+  // Every header of the main section, including the ones above, as written.
+  pub attributes: BTreeMap<String, String>,
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct PluginDeveloper {
+  pub name: String,
+  pub id: String,
+  pub email: String,
+}
+
+// A plugin dependency is an entry from a manifest's Plugin-Dependencies.
+// Optional dependencies are only needed if the plugin is installed anyway.
+#[derive(Clone, Debug)]
+pub struct PluginDependency {
+  pub name: String,
+  pub version: JenkinsPluginVersion,
+  pub optional: bool,
+}
+
+impl PluginManifest {
+
+  pub fn parse(bytes: &[u8]) -> Result<Self, AppError> {
+    let attributes = main_section_lines(bytes)
+      .into_iter()
+      .map(|line| {
+        let text = String::from_utf8(line)
+          .map_err(AppError::ManifestDecodeError)
+          ?;
+        // The spec requires a space after the colon, but be forgiving about
+        // it.
+        text
+          .split_once(":")
+          .map(|(name, value)| {
+            let value = value.strip_prefix(" ").unwrap_or(value);
+            (name.trim().to_string(), value.to_string())
+          })
+          .ok_or(AppError::ManifestLineParseError(text))
+      })
+      .collect::<Result<BTreeMap<String, String>, AppError>>()
+      ?;
+    let text = |name: &str| -> Option<String> {
+      attributes
+        .get(name)
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+    };
+    let version = |name: &str| -> Result<Option<JenkinsPluginVersion>, AppError> {
+      text(name)
+        .map(|v| JenkinsPluginVersion::parse(&v))
+        .transpose()
+    };
+    Ok(PluginManifest {
+      short_name: text("Short-Name"),
+      long_name: text("Long-Name"),
+      plugin_version: text("Plugin-Version"),
+      jenkins_version: version("Jenkins-Version")?,
+      minimum_java_version: text("Minimum-Java-Version"),
+      compatible_since_version: version("Compatible-Since-Version")?,
+      url: text("Url"),
+      plugin_developers: text("Plugin-Developers")
+        .map(|developers| parse_developers(&developers))
+        .unwrap_or_default(),
+      plugin_dependencies: text("Plugin-Dependencies")
+        .map(|dependencies| {
+          dependencies
+            .split(",")
+            .filter(|d| !d.trim().is_empty())
+            .map(from_name_version_string)
+            .collect::<Result<Vec<PluginDependency>, AppError>>()
+        })
+        .transpose()
+        ?
+        .unwrap_or_default(),
+      attributes,
+    })
+  }
+
+}
+
+// Split the raw manifest into the logical (unwrapped) lines of its main
+// section.
+fn main_section_lines(bytes: &[u8]) -> Vec<Vec<u8>> {
+  let mut lines: Vec<Vec<u8>> = vec!();
+  let mut rest = bytes;
+  while !rest.is_empty() {
+    let end = rest
+      .iter()
+      .position(|b| *b == b'\r' || *b == b'\n')
+      .unwrap_or(rest.len());
+    let line = &rest[..end];
+    rest = &rest[end..];
+    if rest.starts_with(b"\r\n") {
+      rest = &rest[2..];
+    } else if !rest.is_empty() {
+      rest = &rest[1..];
+    }
+    if line.is_empty() {
+      break;
+    }
+    match (line.strip_prefix(b" "), lines.last_mut()) {
+      (Some(continuation), Some(previous)) => {
+        previous.extend_from_slice(continuation)
+      },
+      _ => lines.push(line.to_vec()),
+    }
+  }
+  lines
+}
+
+// Developers are listed as comma separated "name:id:email" triples, any of
+// which can be empty.
+fn parse_developers(developers: &str) -> Vec<PluginDeveloper> {
+  developers
+    .split(",")
+    .filter(|d| !d.trim().is_empty())
+    .map(|developer| {
+      let mut fields = developer.splitn(3, ":").map(|f| f.trim().to_string());
+      PluginDeveloper {
+        name: fields.next().unwrap_or_default(),
+        id: fields.next().unwrap_or_default(),
+        email: fields.next().unwrap_or_default(),
+      }
+    })
+    .collect()
+}
+
+fn from_name_version_string(plugin_pair: &str) -> Result<PluginDependency, AppError> {
+  let (name, version_and_resolution) = plugin_pair
+    .split_once(":")
+    .ok_or_else(|| AppError::RemotePluginDeserializeError(plugin_pair.into()))
+    ?;
+  // Versions can be followed by directives such as "resolution:=optional".
+  // That's the only one Jenkins knows about.
+  let mut parts = version_and_resolution.split(";");
+  let version = parts.next().unwrap_or_default();
+  let optional = parts.any(|directive| {
+    directive.trim() == "resolution:=optional"
+  });
+  Ok(PluginDependency {
+    name: name.trim().to_string(),
+    version: JenkinsPluginVersion::parse(version.trim())?,
+    optional,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn parse(text: &str) -> PluginManifest {
+    PluginManifest::parse(text.as_bytes()).unwrap()
+  }
+
+  fn dependencies(manifest: &PluginManifest) -> Vec<(String, String, bool)> {
+    manifest
+      .plugin_dependencies
+      .iter()
+      .map(|d| (d.name.clone(), d.version.to_string(), d.optional))
+      .collect()
+  }
+
+  // Wrap a header the way the JAR tools do: 72 bytes to a line, with each
+  // continuation taking one of them for its leading space.
+  fn wrap(header: &[u8]) -> Vec<u8> {
+    let mut bytes = header[..72.min(header.len())].to_vec();
+    for chunk in header[72.min(header.len())..].chunks(71) {
+      bytes.extend_from_slice(b"\r\n ");
+      bytes.extend_from_slice(chunk);
+    }
+    bytes.extend_from_slice(b"\r\n");
+    bytes
+  }
+
+  #[test]
+  fn wrapped_dependencies() {
+    let mut bytes = b"Short-Name: git\r\n".to_vec();
+    bytes.extend(wrap(concat!(
+      "Plugin-Dependencies: credentials:1337.v60b_d7b_c7b_c9f,",
+      "git-client:5.0.0,mailer:472.vf7c289a_4b_420;resolution:=optional,",
+      "scm-api:698.v8e3b_c788f0a_6",
+    ).as_bytes()));
+    bytes.extend_from_slice(b"\r\n");
+    assert!(bytes.split(|b| *b == b'\n').all(|line| line.len() <= 73));
+    let manifest = PluginManifest::parse(&bytes).unwrap();
+    assert_eq!(manifest.short_name.as_deref(), Some("git"));
+    assert_eq!(dependencies(&manifest), vec!(
+      ("credentials".to_string(), "1337.v60b_d7b_c7b_c9f".to_string(), false),
+      ("git-client".to_string(), "5.0.0".to_string(), false),
+      ("mailer".to_string(), "472.vf7c289a_4b_420".to_string(), true),
+      ("scm-api".to_string(), "698.v8e3b_c788f0a_6".to_string(), false),
+    ));
+  }
+
+  #[test]
+  fn multibyte_character_split_across_wrap() {
+    // "é" is 0xC3 0xA9, and the padding puts the wrap between the two.
+    let header = format!("Long-Name: {}é Plugin", "x".repeat(60));
+    let bytes = wrap(header.as_bytes());
+    let split = [&header.as_bytes()[..71], b"\xC3\r\n \xA9"].concat();
+    assert!(bytes.starts_with(&split));
+    let manifest = PluginManifest::parse(&bytes).unwrap();
+    assert_eq!(
+      manifest.long_name,
+      Some(format!("{}é Plugin", "x".repeat(60))),
+    );
+  }
+
+  #[test]
+  fn line_endings() {
+    for ending in ["\r\n", "\n", "\r"] {
+      let manifest = parse(&[
+        "Short-Name: git",
+        "Long-Name: Git",
+        " plugin",
+        "Jenkins-Version: 2.401.3",
+        "",
+      ].join(ending));
+      assert_eq!(manifest.short_name.as_deref(), Some("git"), "{:?}", ending);
+      assert_eq!(manifest.long_name.as_deref(), Some("Gitplugin"));
+      assert_eq!(
+        manifest.jenkins_version.map(|v| v.to_string()).as_deref(),
+        Some("2.401.3"),
+      );
+    }
+  }
+
+  #[test]
+  fn main_section_only() {
+    let manifest = parse(concat!(
+      "Short-Name: git\n",
+      "\n",
+      "Name: WEB-INF/lib/git.jar\n",
+      "Short-Name: not-git\n",
+      "Plugin-Dependencies: credentials:1.0\n",
+    ));
+    assert_eq!(manifest.short_name.as_deref(), Some("git"));
+    assert!(manifest.plugin_dependencies.is_empty());
+    assert!(!manifest.attributes.contains_key("Name"));
+  }
+
+  #[test]
+  fn blank_dependencies() {
+    let manifest = parse("Short-Name: git\nPlugin-Dependencies: \n");
+    assert!(manifest.plugin_dependencies.is_empty());
+    assert_eq!(
+      manifest.attributes.get("Plugin-Dependencies").map(String::as_str),
+      Some(""),
+    );
+  }
+
+  #[test]
+  fn developers_with_empty_fields() {
+    let manifest = parse(
+      "Plugin-Developers: Jane Doe:jdoe:,:anonymous:,Bob::bob@example.com\n",
+    );
+    let developer = |name: &str, id: &str, email: &str| PluginDeveloper {
+      name: name.to_string(),
+      id: id.to_string(),
+      email: email.to_string(),
+    };
+    assert_eq!(manifest.plugin_developers, vec!(
+      developer("Jane Doe", "jdoe", ""),
+      developer("", "anonymous", ""),
+      developer("Bob", "", "bob@example.com"),
+    ));
+  }
+
+  #[test]
+  fn malformed_line() {
+    assert!(PluginManifest::parse(b"Short-Name git\n").is_err());
+  }
+
+}
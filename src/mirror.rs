@@ -0,0 +1,79 @@
+// Plugins are downloaded from a list of mirrors, tried in order.  Each mirror is
+// a URL template with these placeholders:
+//
+// - {name}: The plugin's short name, such as "git".
+// - {version}: The plugin version.
+// - {groupId}: The plugin's Maven group, such as "org.jenkins-ci.plugins".
+// - {groupPath}: The Maven group as a path, such as "org/jenkins-ci/plugins".
+//
+// The get.jenkins.io layout is:
+//   https://get.jenkins.io/plugins/{name}/{version}/{name}.hpi
+// and a Maven repository (such as an Artifactory remote of
+// https://repo.jenkins-ci.org/releases) is:
+//   https://example.com/artifactory/jenkins/{groupPath}/{name}/{version}/{name}-{version}.hpi
+
+use crate::jenkins_plugin_version::JenkinsPluginVersion;
+
+pub const DEFAULT_MIRROR: &str =
+  "https://get.jenkins.io/plugins/{name}/{version}/{name}.hpi";
+
+// Most plugins live here, so it's what we assume when we can't look it up.
+pub const DEFAULT_GROUP_ID: &str = "org.jenkins-ci.plugins";
+
+pub fn mirror_needs_group(template: &str) -> bool {
+  template.contains("{groupId}") || template.contains("{groupPath}")
+}
+
+pub fn mirror_url(
+  template: &str,
+  name: &str,
+  version: &JenkinsPluginVersion,
+  group_id: &str,
+) -> String {
+  template
+    .replace("{name}", name)
+    .replace("{version}", &version.to_string())
+    .replace("{groupId}", group_id)
+    .replace("{groupPath}", &group_id.replace(".", "/"))
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn url(template: &str, group_id: &str) -> String {
+    mirror_url(
+      template,
+      "git",
+      &JenkinsPluginVersion::parse("5.2.2").unwrap(),
+      group_id,
+    )
+  }
+
+  #[test]
+  fn default_mirror() {
+    assert_eq!(
+      url(DEFAULT_MIRROR, DEFAULT_GROUP_ID),
+      "https://get.jenkins.io/plugins/git/5.2.2/git.hpi",
+    );
+    assert!(!mirror_needs_group(DEFAULT_MIRROR));
+  }
+
+  #[test]
+  fn maven_layout() {
+    let template =
+      "https://example.com/{groupPath}/{name}/{version}/{name}-{version}.hpi";
+    assert!(mirror_needs_group(template));
+    assert_eq!(
+      url(template, "io.jenkins.plugins"),
+      "https://example.com/io/jenkins/plugins/git/5.2.2/git-5.2.2.hpi",
+    );
+    assert!(mirror_needs_group("https://example.com/{groupId}/{name}.hpi"));
+    assert_eq!(
+      url("https://example.com/{groupId}/{name}.hpi", DEFAULT_GROUP_ID),
+      "https://example.com/org.jenkins-ci.plugins/git.hpi",
+    );
+  }
+
+}
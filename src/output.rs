@@ -0,0 +1,364 @@
+// The same resolved set goes to Puppet, Docker images and Kubernetes, each of
+// which wants it in its own shape.  Every shape is an OutputWriter, so adding
+// one is a matter of writing another and listing it in OutputFormat.
+
+use std::{collections::BTreeMap, io::ErrorKind};
+
+use log::*;
+use serde::Serialize;
+use similar::TextDiff;
+
+use crate::{
+  cli::OutputFormat,
+  error::AppError,
+  input::FlatPackage,
+  jenkins_core::MinimumCore,
+  jenkins_plugin_version::JenkinsPluginVersion,
+  update_center::hex_to_base64,
+};
+
+// Everything a writer gets to work with.  Plugins are in name order, and so
+// should be anything written from them, so the same input always gives the
+// same bytes.
+pub struct Resolution<'a> {
+  pub resolved: &'a BTreeMap<String, FlatPackage>,
+  // Only set with --emit-minimum-core.  Formats with nowhere to put it leave
+  // it out.
+  pub minimum_core: Option<MinimumCore>,
+}
+
+// Writers return the whole document, ending in a newline.
+pub trait OutputWriter {
+  fn write(&self, resolution: &Resolution) -> Result<String, AppError>;
+}
+
+// Settings that only some writers care about.
+pub struct OutputOptions {
+  pub helm_disable_latest: bool,
+}
+
+pub fn output_writer(
+  format: OutputFormat,
+  options: &OutputOptions,
+) -> Box<dyn OutputWriter> {
+  match format {
+    OutputFormat::Hiera => Box::new(HieraWriter),
+    OutputFormat::PluginsTxt => Box::new(PluginsTxtWriter),
+    OutputFormat::PluginsYaml => Box::new(PluginsYamlWriter),
+    OutputFormat::Json => Box::new(JsonWriter),
+    OutputFormat::Nix => Box::new(NixWriter),
+    OutputFormat::Helm => Box::new(HelmWriter {
+      disable_latest: options.helm_disable_latest,
+    }),
+  }
+}
+
+// Hiera data for the puppet-jenkins module.
+pub struct HieraWriter;
+
+#[derive(Serialize)]
+struct HieraOutput<'a> {
+  #[serde(rename = "jenkins::plugin_hash")]
+  plugin_hash: &'a BTreeMap<String, FlatPackage>,
+  #[serde(
+    rename = "jenkins_plugin_graph::minimum_jenkins_version",
+    skip_serializing_if = "Option::is_none",
+  )]
+  minimum_jenkins_version: &'a Option<MinimumCore>,
+}
+
+impl OutputWriter for HieraWriter {
+
+  fn write(&self, resolution: &Resolution) -> Result<String, AppError> {
+    serde_yaml::to_string(&HieraOutput {
+      plugin_hash: resolution.resolved,
+      minimum_jenkins_version: &resolution.minimum_core,
+    })
+      .map_err(AppError::YamlSerializationError)
+  }
+
+}
+
+// A plugins.txt of "name:version" lines, as jenkins-plugin-cli and the
+// official Docker image read them.
+pub struct PluginsTxtWriter;
+
+impl OutputWriter for PluginsTxtWriter {
+
+  fn write(&self, resolution: &Resolution) -> Result<String, AppError> {
+    Ok(
+      resolution
+        .resolved
+        .values()
+        .map(|p| format!("{}:{}\n", p.name, p.version))
+        .collect()
+    )
+  }
+
+}
+
+// The plugins.yaml of the plugin installation manager (jenkins-plugin-cli):
+// https://github.com/jenkinsci/plugin-installation-manager-tool#plugin-input-format
+pub struct PluginsYamlWriter;
+
+#[derive(Serialize)]
+struct PluginsYaml<'a> {
+  plugins: Vec<PluginsYamlPlugin<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PluginsYamlPlugin<'a> {
+  artifact_id: &'a str,
+  source: PluginsYamlSource<'a>,
+}
+
+#[derive(Serialize)]
+struct PluginsYamlSource<'a> {
+  version: &'a JenkinsPluginVersion,
+}
+
+impl OutputWriter for PluginsYamlWriter {
+
+  fn write(&self, resolution: &Resolution) -> Result<String, AppError> {
+    serde_yaml::to_string(&PluginsYaml {
+      plugins: resolution
+        .resolved
+        .values()
+        .map(|p| PluginsYamlPlugin {
+          artifact_id: &p.name,
+          source: PluginsYamlSource { version: &p.version },
+        })
+        .collect(),
+    })
+      .map_err(AppError::YamlSerializationError)
+  }
+
+}
+
+// The same data as the Hiera output, without the Puppet class names.
+pub struct JsonWriter;
+
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+  plugins: &'a BTreeMap<String, FlatPackage>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  minimum_jenkins_version: &'a Option<MinimumCore>,
+}
+
+impl OutputWriter for JsonWriter {
+
+  fn write(&self, resolution: &Resolution) -> Result<String, AppError> {
+    serde_json::to_string_pretty(&JsonOutput {
+      plugins: resolution.resolved,
+      minimum_jenkins_version: &resolution.minimum_core,
+    })
+      .map(|json| json + "\n")
+      .map_err(AppError::JsonSerializationError)
+  }
+
+}
+
+// A values.yaml fragment for the official Jenkins Helm chart.
+pub struct HelmWriter {
+  // The chart upgrades plugins to their latest versions by default, which
+  // would undo our resolution.
+  pub disable_latest: bool,
+}
+
+#[derive(Serialize)]
+struct HelmValues {
+  controller: HelmController,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct HelmController {
+  install_plugins: Vec<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  install_latest_plugins: Option<bool>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  install_latest_specified_plugins: Option<bool>,
+}
+
+impl OutputWriter for HelmWriter {
+
+  fn write(&self, resolution: &Resolution) -> Result<String, AppError> {
+    let disable = self.disable_latest.then_some(false);
+    serde_yaml::to_string(&HelmValues {
+      controller: HelmController {
+        install_plugins: resolution
+          .resolved
+          .values()
+          .map(|p| format!("{}:{}", p.name, p.version))
+          .collect(),
+        install_latest_plugins: disable,
+        install_latest_specified_plugins: disable,
+      },
+    })
+      .map_err(AppError::YamlSerializationError)
+  }
+
+}
+
+// A Nix function from fetchurl to an attrset of the plugin archives, for
+// services.jenkins.plugins:
+//
+// services.jenkins.plugins = import ./plugins.nix { inherit (pkgs) fetchurl; };
+pub struct NixWriter;
+
+impl OutputWriter for NixWriter {
+
+  fn write(&self, resolution: &Resolution) -> Result<String, AppError> {
+    let mut lines = vec!(
+      "{ fetchurl }:".to_string(),
+      "{".to_string(),
+    );
+    for package in resolution.resolved.values() {
+      lines.push(format!("  {} = fetchurl {{", nix_quote(&package.name)));
+      lines.push(format!("    url = {};", nix_quote(&package.url)));
+      // Nix wants the hash in SRI form, which is base64 rather than hex.
+      lines.push(format!(
+        "    hash = {};",
+        nix_quote(&format!(
+          "{}-{}",
+          package.digest_type,
+          hex_to_base64(&package.digest_string),
+        )),
+      ));
+      lines.push("  };".to_string());
+    }
+    lines.push("}".to_string());
+    Ok(lines.join("\n") + "\n")
+  }
+
+}
+
+fn nix_quote(s: &str) -> String {
+  format!(
+    "\"{}\"",
+    s.replace('\\', "\\\\").replace('"', "\\\"").replace("${", "\\${"),
+  )
+}
+
+// Write next to the file and rename over it, so a reader (or a failure halfway)
+// never leaves it half written.
+pub fn output_write(path: &str, text: &str) -> Result<(), AppError> {
+  let temporary = format!("{}.{}.tmp", path, std::process::id());
+  std::fs::write(&temporary, text)
+    .and_then(|_| std::fs::rename(&temporary, path))
+    .map_err(|e| {
+      let _ = std::fs::remove_file(&temporary);
+      AppError::OutputWriteError(path.to_string(), e)
+    })
+}
+
+// For CI: fail if the file isn't what we would write, and show how.  A missing
+// file is as stale as any other.
+pub fn output_check(path: &str, text: &str) -> Result<(), AppError> {
+  let existing = match std::fs::read_to_string(path) {
+    Ok(existing) => existing,
+    Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+    Err(e) => return Err(AppError::OutputReadError(path.to_string(), e)),
+  };
+  if existing == text {
+    info!("{} is up to date.", path);
+    return Ok(());
+  }
+  print!(
+    "{}",
+    TextDiff::from_lines(existing.as_str(), text)
+      .unified_diff()
+      .header(path, "resolved"),
+  );
+  Err(AppError::OutputStaleError(path.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn package(name: &str, version: &str) -> FlatPackage {
+    let version = JenkinsPluginVersion::parse(version).unwrap();
+    FlatPackage {
+      name: name.to_string(),
+      version: version.clone(),
+      digest_string:
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+          .to_string(),
+      digest_type: "sha256".to_string(),
+      pin: true,
+      requested: version,
+      jenkins_version: None,
+      url: format!("https://get.jenkins.io/plugins/{}/{}.hpi", name, name),
+    }
+  }
+
+  // A path in the temporary directory, with nothing there yet.
+  fn temporary(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!(
+      "jenkins-plugin-graph-{}-{}",
+      std::process::id(),
+      name,
+    ));
+    let _ = std::fs::remove_file(&path);
+    path.to_string_lossy().to_string()
+  }
+
+  #[test]
+  fn nix_quoting() {
+    assert_eq!(nix_quote("git"), "\"git\"");
+    assert_eq!(nix_quote("a\"b"), "\"a\\\"b\"");
+    assert_eq!(nix_quote("a\\b"), "\"a\\\\b\"");
+    assert_eq!(nix_quote("${x}"), "\"\\${x}\"");
+    assert_eq!(nix_quote("$x"), "\"$x\"");
+  }
+
+  #[test]
+  fn nix() {
+    let resolved = BTreeMap::from([
+      ("git".to_string(), package("git", "5.2.2")),
+    ]);
+    let text = NixWriter
+      .write(&Resolution { resolved: &resolved, minimum_core: None })
+      .unwrap();
+    assert_eq!(text, [
+      "{ fetchurl }:",
+      "{",
+      "  \"git\" = fetchurl {",
+      "    url = \"https://get.jenkins.io/plugins/git/git.hpi\";",
+      "    hash = \"sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=\";",
+      "  };",
+      "}",
+      "",
+    ].join("\n"));
+  }
+
+  #[test]
+  fn check() {
+    let path = temporary("check");
+    assert!(matches!(
+      output_check(&path, "a\n"),
+      Err(AppError::OutputStaleError(_)),
+    ));
+    output_write(&path, "a\n").unwrap();
+    assert!(output_check(&path, "a\n").is_ok());
+    assert!(matches!(
+      output_check(&path, "b\n"),
+      Err(AppError::OutputStaleError(_)),
+    ));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\n");
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn write_replaces() {
+    let path = temporary("write");
+    output_write(&path, "a\n").unwrap();
+    output_write(&path, "b\n").unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "b\n");
+    std::fs::remove_file(&path).unwrap();
+  }
+
+}
@@ -0,0 +1,122 @@
+// The Jenkins update center publishes plugin-versions.json, which lists every
+// released version of every plugin.  We use it to turn version constraints into
+// concrete versions.
+
+use std::{collections::HashMap, sync::{Mutex, OnceLock}};
+
+use serde::Deserialize;
+
+use crate::{
+  error::AppError,
+  jenkins_plugin_version::JenkinsPluginVersion,
+  update_center::update_center_text,
+};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PluginVersions {
+  pub plugins: HashMap<String, HashMap<String, PluginVersionEntry>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PluginVersionEntry {
+  pub version: String,
+  // The Maven coordinates, as "group:artifact:version".
+  pub gav: Option<String>,
+  // The same as the Jenkins-Version and Plugin-Dependencies of the manifest,
+  // which lets the solver weigh versions without downloading them.
+  #[serde(rename = "requiredCore")]
+  pub required_core: Option<String>,
+  #[serde(default)]
+  pub dependencies: Vec<PluginVersionDependency>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct PluginVersionDependency {
+  pub name: String,
+  pub version: String,
+  #[serde(default)]
+  pub optional: bool,
+}
+
+// Loads the listing the first time something asks for it, so runs that never
+// need it don't pay for downloading it.  Downloads happen on several threads,
+// so only one of them gets to do the loading.
+pub struct PluginVersionsSource {
+  cache_dir: String,
+  location: String,
+  loaded: OnceLock<PluginVersions>,
+  loading: Mutex<()>,
+}
+
+impl PluginVersionsSource {
+
+  pub fn new(cache_dir: &str, location: &str) -> Self {
+    PluginVersionsSource {
+      cache_dir: cache_dir.to_string(),
+      location: location.to_string(),
+      loaded: OnceLock::new(),
+      loading: Mutex::new(()),
+    }
+  }
+
+  pub fn get(&self) -> Result<&PluginVersions, AppError> {
+    if let Some(listing) = self.loaded.get() {
+      return Ok(listing);
+    }
+    let _guard = self.loading.lock().unwrap_or_else(|e| e.into_inner());
+    match self.loaded.get() {
+      Some(listing) => Ok(listing),
+      None => {
+        let listing = plugin_versions_load(&self.cache_dir, &self.location)?;
+        Ok(self.loaded.get_or_init(|| listing))
+      },
+    }
+  }
+
+}
+
+impl PluginVersions {
+
+  // Every published version of the named plugin.  Versions we cannot parse are
+  // skipped rather than failing the whole listing.
+  pub fn versions(&self, name: &str) -> Vec<JenkinsPluginVersion> {
+    self
+      .plugins
+      .get(name)
+      .map(|versions| {
+        versions
+          .values()
+          .filter_map(|entry| JenkinsPluginVersion::parse(&entry.version).ok())
+          .collect()
+      })
+      .unwrap_or_default()
+  }
+
+  // The Maven group of the named plugin.  Plugins rarely change groups, so if
+  // the version isn't listed any other version will do.
+  pub fn group_id(
+    &self,
+    name: &str,
+    version: &JenkinsPluginVersion,
+  ) -> Option<String> {
+    let versions = self.plugins.get(name)?;
+    versions
+      .get(&version.to_string())
+      .into_iter()
+      .chain(versions.values())
+      .filter_map(|entry| entry.gav.as_ref())
+      .find_map(|gav| gav.split(":").next().map(|g| g.to_string()))
+  }
+
+}
+
+pub fn plugin_versions_load(
+  cache_dir: &str,
+  location: &str,
+) -> Result<PluginVersions, AppError> {
+  let text = update_center_text(cache_dir, location)?;
+  serde_json::from_str(&text)
+    .map_err(|e| {
+      AppError::UpdateCenterDeserializeError(location.to_string(), e)
+    })
+}
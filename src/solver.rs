@@ -0,0 +1,579 @@
+// Greedy resolution takes the highest version anything asks for, and can't step
+// back when that version turns out to need something nobody can have.  The
+// solver searches the versions published in plugin-versions.json instead,
+// backing out of any pick that leads to a dead end, and explains what was in
+// the way when there is no way through.
+//
+// - Plugins from our input are tried newest first, and everything else oldest
+//   first.  Where greedy resolution gets an answer, this usually gets the
+//   same one.
+// - The plugin to decide next is the one with the fewest versions left, so dead
+//   ends turn up before much has been built on top of them.
+// - Dependencies are minimums, as they are for Jenkins.  What optional ones do
+//   depends on OptionalDependencies, as it does for the walk.
+// - The listing carries each version's dependencies and core requirement, so
+//   nothing is downloaded until the search is over.
+// - It's a plain depth first search.  Nothing is learned from a dead end, so
+//   the same one can be walked into again from another branch, and only the
+//   dead ends of the last branch tried at each level are explained.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use log::*;
+
+use crate::{
+  cli::OptionalDependencies,
+  error::AppError,
+  jenkins_plugin_version::JenkinsPluginVersion,
+  manifest::PluginDependency,
+  plugin_versions::PluginVersions,
+  version_constraint::{
+    VersionBound,
+    VersionConstraint,
+    VersionConstraintBetween,
+  },
+};
+
+// A listing shaped just wrong can take the search forever, so give up after
+// this many picks.
+const SOLVER_STEPS_MAX: usize = 100_000;
+
+// How many rejected versions of a plugin to name in an explanation.
+const EXPLANATION_VERSIONS_MAX: usize = 5;
+
+struct Candidate {
+  version: JenkinsPluginVersion,
+  required_core: Option<JenkinsPluginVersion>,
+  dependencies: Vec<PluginDependency>,
+}
+
+// A constraint on a plugin, and who put it there.
+struct Requirement {
+  // "name version" of the dependent plugin, or "input" for our input file.
+  source: String,
+  constraint: VersionConstraint,
+}
+
+// Why a version that meets every requirement still can't be picked.
+enum Rejection {
+  Core(JenkinsPluginVersion),
+  Dependency(PluginDependency, JenkinsPluginVersion),
+}
+
+struct Search<'a> {
+  listing: &'a PluginVersions,
+  roots: &'a BTreeMap<String, VersionConstraint>,
+  optional_dependencies: OptionalDependencies,
+  jenkins_version: Option<&'a JenkinsPluginVersion>,
+  // Every published version of each plugin looked at so far, oldest first.
+  candidates: HashMap<String, Vec<Candidate>>,
+  // The index into candidates picked for each plugin so far.
+  decided: HashMap<String, usize>,
+  // The dead ends along the branch tried last.
+  explanations: Vec<String>,
+  steps: usize,
+}
+
+// Pick a version of every plugin our input needs, directly or not, such that
+// every constraint holds and, given a target, every plugin runs on that core.
+pub fn solve(
+  listing: &PluginVersions,
+  roots: &BTreeMap<String, VersionConstraint>,
+  optional_dependencies: OptionalDependencies,
+  jenkins_version: Option<&JenkinsPluginVersion>,
+) -> Result<HashMap<String, JenkinsPluginVersion>, AppError> {
+  let mut search = Search {
+    listing,
+    roots,
+    optional_dependencies,
+    jenkins_version,
+    candidates: HashMap::new(),
+    decided: HashMap::new(),
+    explanations: vec!(),
+    steps: 0,
+  };
+  if search.search()? {
+    debug!("Solved after {} step(s).", search.steps);
+    Ok(
+      search
+        .decided
+        .iter()
+        .map(|(name, index)| {
+          (name.clone(), search.candidates[name][*index].version.clone())
+        })
+        .collect()
+    )
+  } else {
+    Err(AppError::NoSolutionError(search.explanations))
+  }
+}
+
+impl Search<'_> {
+
+  fn search(&mut self) -> Result<bool, AppError> {
+    self.steps += 1;
+    if self.steps > SOLVER_STEPS_MAX {
+      return Err(AppError::SolverStepsExceededError(SOLVER_STEPS_MAX));
+    }
+    let pending = self.pending();
+    for name in &pending {
+      self.load(name);
+    }
+    let next = pending
+      .into_iter()
+      .map(|name| {
+        let viable = self.viable(&name);
+        (name, viable)
+      })
+      .min_by_key(|(_, viable)| viable.len());
+    let (name, viable) = match next {
+      Some(next) => next,
+      None => return Ok(true),
+    };
+    if viable.is_empty() {
+      let explanation = self.explain(&name);
+      trace!("Dead end: {}", explanation);
+      if !self.explanations.contains(&explanation) {
+        self.explanations.push(explanation);
+      }
+      return Ok(false);
+    }
+    // Whatever went wrong with the versions tried before is no longer the
+    // reason, so only the last one's dead ends are kept.
+    let explained = self.explanations.len();
+    for index in viable {
+      self.explanations.truncate(explained);
+      trace!("Trying {} {}.", name, self.candidates[&name][index].version);
+      self.decided.insert(name.clone(), index);
+      if self.search()? {
+        return Ok(true);
+      }
+      self.decided.remove(&name);
+    }
+    Ok(false)
+  }
+
+  fn load(&mut self, name: &str) {
+    if self.candidates.contains_key(name) {
+      return;
+    }
+    let mut candidates = self
+      .listing
+      .plugins
+      .get(name)
+      .into_iter()
+      .flat_map(|versions| versions.values())
+      .filter_map(|entry| {
+        Some(Candidate {
+          version: JenkinsPluginVersion::parse(&entry.version).ok()?,
+          required_core: entry
+            .required_core
+            .as_ref()
+            .and_then(|v| JenkinsPluginVersion::parse(v).ok()),
+          dependencies: entry
+            .dependencies
+            .iter()
+            .filter_map(|d| {
+              Some(PluginDependency {
+                name: d.name.clone(),
+                version: JenkinsPluginVersion::parse(&d.version).ok()?,
+                optional: d.optional,
+              })
+            })
+            .collect(),
+        })
+      })
+      .collect::<Vec<Candidate>>();
+    candidates.sort_by(|a, b| a.version.cmp(&b.version));
+    self.candidates.insert(name.to_string(), candidates);
+  }
+
+  fn decided_candidates(&self) -> impl Iterator<Item = &Candidate> {
+    self
+      .decided
+      .iter()
+      .map(|(name, index)| &self.candidates[name][*index])
+  }
+
+  // Whether a dependency constrains its plugin at all.
+  fn constrains(&self, dependency: &PluginDependency) -> bool {
+    !dependency.optional
+      || self.optional_dependencies != OptionalDependencies::Exclude
+  }
+
+  // Whether a dependency pulls its plugin in, rather than only constraining it
+  // should something else pull it in.
+  fn requires(&self, dependency: &PluginDependency) -> bool {
+    !dependency.optional
+      || self.optional_dependencies == OptionalDependencies::All
+  }
+
+  // Plugins that are needed but not yet decided, in name order so the search
+  // goes the same way every time.
+  fn pending(&self) -> BTreeSet<String> {
+    self
+      .roots
+      .keys()
+      .cloned()
+      .chain(
+        self
+          .decided_candidates()
+          .flat_map(|c| c.dependencies.iter())
+          .filter(|d| self.requires(d))
+          .map(|d| d.name.clone())
+      )
+      .filter(|name| !self.decided.contains_key(name))
+      .collect()
+  }
+
+  fn requirements(&self, name: &str) -> Vec<Requirement> {
+    let mut requirements = self
+      .roots
+      .get(name)
+      .map(|constraint| Requirement {
+        source: "input".to_string(),
+        constraint: constraint.clone(),
+      })
+      .into_iter()
+      .collect::<Vec<Requirement>>();
+    let mut dependents = self
+      .decided
+      .iter()
+      .flat_map(|(dependent, index)| {
+        let candidate = &self.candidates[dependent][*index];
+        candidate
+          .dependencies
+          .iter()
+          .filter(|d| d.name == name && self.constrains(d))
+          .map(move |d| Requirement {
+            source: format!("{} {}", dependent, candidate.version),
+            constraint: at_least(&d.version),
+          })
+      })
+      .collect::<Vec<Requirement>>();
+    dependents.sort_by(|a, b| a.source.cmp(&b.source));
+    requirements.extend(dependents);
+    requirements
+  }
+
+  fn rejection(&self, candidate: &Candidate) -> Option<Rejection> {
+    if let (Some(required), Some(target)) =
+      (&candidate.required_core, self.jenkins_version) {
+      if required > target {
+        return Some(Rejection::Core(required.clone()));
+      }
+    }
+    candidate
+      .dependencies
+      .iter()
+      .filter(|d| self.constrains(d))
+      .find_map(|d| {
+        self
+          .decided
+          .get(&d.name)
+          .map(|index| &self.candidates[&d.name][*index].version)
+          .filter(|decided| **decided < d.version)
+          .map(|decided| Rejection::Dependency(d.clone(), decided.clone()))
+      })
+  }
+
+  // The versions of a plugin that can still be picked, best first.
+  fn viable(&self, name: &str) -> Vec<usize> {
+    let requirements = self.requirements(name);
+    let candidates = &self.candidates[name];
+    let mut viable = (0..candidates.len())
+      .filter(|i| {
+        let candidate = &candidates[*i];
+        requirements
+          .iter()
+          .all(|r| r.constraint.satisfied_by(&candidate.version))
+          && self.rejection(candidate).is_none()
+      })
+      .collect::<Vec<usize>>();
+    if self.roots.contains_key(name) {
+      viable.reverse();
+    }
+    viable
+  }
+
+  fn explain(&self, name: &str) -> String {
+    let candidates = &self.candidates[name];
+    if candidates.is_empty() {
+      return format!("{} has no versions in the plugin versions listing.", name);
+    }
+    let requirements = self.requirements(name);
+    let mut lines = vec!(format!("No version of {} satisfies:", name));
+    for requirement in &requirements {
+      lines.push(format!(
+        "  {} needs {}",
+        requirement.source,
+        requirement.constraint,
+      ));
+    }
+    let rejections = candidates
+      .iter()
+      .rev()
+      .filter(|c| {
+        requirements.iter().all(|r| r.constraint.satisfied_by(&c.version))
+      })
+      .filter_map(|c| self.rejection(c).map(|r| (c, r)))
+      .collect::<Vec<(&Candidate, Rejection)>>();
+    if rejections.is_empty() {
+      lines.push(format!(
+        "  and no published version meets all of those (newest is {})",
+        candidates[candidates.len() - 1].version,
+      ));
+    }
+    for (candidate, rejection) in
+      rejections.iter().take(EXPLANATION_VERSIONS_MAX) {
+      lines.push(format!(
+        "  {}",
+        self.describe(name, candidate, rejection),
+      ));
+      // A dependency that was picked too low is only half the story, so say
+      // what kept its newer versions out too.
+      if let Rejection::Dependency(dependency, _) = rejection {
+        lines.extend(
+          self.candidates[&dependency.name]
+            .iter()
+            .filter(|c| c.version >= dependency.version)
+            .filter_map(|c| self.unpickable(&dependency.name, c))
+            .take(EXPLANATION_VERSIONS_MAX)
+            .map(|reason| format!("    because {}", reason))
+        );
+      }
+    }
+    if rejections.len() > EXPLANATION_VERSIONS_MAX {
+      lines.push(format!(
+        "  and {} older versions",
+        rejections.len() - EXPLANATION_VERSIONS_MAX,
+      ));
+    }
+    lines.join("\n")
+  }
+
+  // Why this version of a plugin can't be picked alongside the others, if it
+  // can't.
+  fn unpickable(&self, name: &str, candidate: &Candidate) -> Option<String> {
+    self
+      .requirements(name)
+      .into_iter()
+      .find(|r| !r.constraint.satisfied_by(&candidate.version))
+      .map(|r| format!(
+        "{} needs {} {}, not {}",
+        r.source,
+        name,
+        r.constraint,
+        candidate.version,
+      ))
+      .or_else(|| {
+        self
+          .rejection(candidate)
+          .map(|rejection| self.describe(name, candidate, &rejection))
+      })
+  }
+
+  fn describe(
+    &self,
+    name: &str,
+    candidate: &Candidate,
+    rejection: &Rejection,
+  ) -> String {
+    match rejection {
+      Rejection::Core(required) => format!(
+        "{} {} needs Jenkins {}, newer than {}",
+        name,
+        candidate.version,
+        required,
+        self.jenkins_version.map(|v| v.to_string()).unwrap_or_default(),
+      ),
+      Rejection::Dependency(dependency, decided) => format!(
+        "{} {} needs {} {} but {} {} was picked",
+        name,
+        candidate.version,
+        dependency.name,
+        at_least(&dependency.version),
+        dependency.name,
+        decided,
+      ),
+    }
+  }
+
+}
+
+fn at_least(version: &JenkinsPluginVersion) -> VersionConstraint {
+  VersionConstraint::Between(VersionConstraintBetween {
+    version_lower_bound: Some(VersionBound {
+      version: version.clone(),
+      inclusive: true,
+    }),
+    version_upper_bound: None,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+
+  use serde_json::json;
+
+  use super::*;
+
+  fn listing(plugins: serde_json::Value) -> PluginVersions {
+    serde_json::from_value(json!({ "plugins": plugins })).unwrap()
+  }
+
+  fn roots(
+    constraints: &[(&str, &str)],
+  ) -> BTreeMap<String, VersionConstraint> {
+    constraints
+      .iter()
+      .map(|(name, constraint)| {
+        (name.to_string(), VersionConstraint::parse(constraint).unwrap())
+      })
+      .collect()
+  }
+
+  fn solved(
+    listing: &PluginVersions,
+    roots: &BTreeMap<String, VersionConstraint>,
+    jenkins_version: &str,
+  ) -> Result<BTreeMap<String, String>, Vec<String>> {
+    let jenkins_version = JenkinsPluginVersion::parse(jenkins_version).unwrap();
+    match solve(
+      listing,
+      roots,
+      OptionalDependencies::IfRequired,
+      Some(&jenkins_version),
+    ) {
+      Ok(solution) => Ok(
+        solution
+          .into_iter()
+          .map(|(name, version)| (name, version.to_string()))
+          .collect()
+      ),
+      Err(AppError::NoSolutionError(explanations)) => Err(explanations),
+      Err(e) => panic!("{:?}", e),
+    }
+  }
+
+  fn versions(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+    pairs
+      .iter()
+      .map(|(name, version)| (name.to_string(), version.to_string()))
+      .collect()
+  }
+
+  // a 2 needs a b that needs a newer core than we have, so the search has to
+  // back out of a 2 and settle for a 1.
+  fn core_too_old() -> serde_json::Value {
+    json!({
+      "a": {
+        "1": {
+          "version": "1",
+          "dependencies": [{ "name": "b", "version": "1" }],
+        },
+        "2": {
+          "version": "2",
+          "dependencies": [{ "name": "b", "version": "2" }],
+        },
+      },
+      "b": {
+        "1": { "version": "1", "requiredCore": "1.0" },
+        "2": { "version": "2", "requiredCore": "3.0" },
+      },
+    })
+  }
+
+  #[test]
+  fn backtracks() {
+    assert_eq!(
+      solved(&listing(core_too_old()), &roots(&[("a", "latest")]), "2.0"),
+      Ok(versions(&[("a", "1"), ("b", "1")])),
+    );
+    assert_eq!(
+      solved(&listing(core_too_old()), &roots(&[("a", "latest")]), "3.0"),
+      Ok(versions(&[("a", "2"), ("b", "2")])),
+    );
+  }
+
+  #[test]
+  fn optional_dependencies_only_constrain() {
+    let listing = listing(json!({
+      "a": {
+        "1": {
+          "version": "1",
+          "dependencies": [{ "name": "b", "version": "2", "optional": true }],
+        },
+      },
+      "b": {
+        "1": { "version": "1" },
+        "2": { "version": "2" },
+      },
+    }));
+    assert_eq!(
+      solved(&listing, &roots(&[("a", "latest")]), "2.0"),
+      Ok(versions(&[("a", "1")])),
+    );
+    assert_eq!(
+      solved(&listing, &roots(&[("a", "latest"), ("b", "latest")]), "2.0"),
+      Ok(versions(&[("a", "1"), ("b", "2")])),
+    );
+    assert!(
+      solved(&listing, &roots(&[("a", "latest"), ("b", "1")]), "2.0").is_err(),
+    );
+  }
+
+  #[test]
+  fn explains() {
+    assert_eq!(
+      solved(&listing(core_too_old()), &roots(&[("a", "2")]), "2.0"),
+      Err(vec!(
+        [
+          "No version of b satisfies:",
+          "  a 2 needs >= 2",
+          "  b 2 needs Jenkins 3.0, newer than 2.0",
+        ].join("\n"),
+      )),
+    );
+    assert_eq!(
+      solved(&listing(core_too_old()), &roots(&[("c", "latest")]), "2.0"),
+      Err(vec!(
+        "c has no versions in the plugin versions listing.".to_string(),
+      )),
+    );
+  }
+
+  #[test]
+  fn explains_the_last_branch() {
+    // a 2 runs into b, and a 1 runs into c.  Only the last is explained.
+    let listing = listing(json!({
+      "a": {
+        "1": {
+          "version": "1",
+          "dependencies": [{ "name": "c", "version": "2" }],
+        },
+        "2": {
+          "version": "2",
+          "dependencies": [{ "name": "b", "version": "2" }],
+        },
+      },
+      "b": {
+        "2": { "version": "2", "requiredCore": "3.0" },
+      },
+      "c": {
+        "1": { "version": "1" },
+      },
+    }));
+    assert_eq!(
+      solved(&listing, &roots(&[("a", "latest")]), "2.0"),
+      Err(vec!(
+        [
+          "No version of c satisfies:",
+          "  a 1 needs >= 2",
+          "  and no published version meets all of those (newest is 1)",
+        ].join("\n"),
+      )),
+    );
+  }
+
+}
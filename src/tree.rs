@@ -0,0 +1,296 @@
+// Print the graph the way cargo tree does, so a reviewer can take in the whole
+// thing at once.  Each plugin shows the version it resolved to, and the version
+// its dependent asked for when that's different:
+//
+// git 5.3.0
+// ├── credentials 1337.v60b_d7b_c7b_c9f
+// │   └── structs 325.v1
+// └── scm-api 690.v1 (asked for 680.v1)
+//     └── structs 325.v1
+//
+// A plugin that was already printed with its dependencies is marked (*) instead
+// of being printed again, except at the top.  Upgrades (asked for less than
+// resolved) are yellow, and unmet asks (asked for more than resolved, which a
+// pinned input allows) are red.
+
+use std::collections::HashMap;
+
+use owo_colors::OwoColorize;
+
+use crate::{
+  error::AppError,
+  input::{graph_edges, EdgeFilter, SatisfiedPackage},
+  jenkins_plugin_version::JenkinsPluginVersion,
+};
+
+pub struct TreeOptions {
+  // How many levels below the top to print, or everything if None.
+  pub depth: Option<usize>,
+  pub color: bool,
+}
+
+// A package to print, and what to say about how it was reached.
+type Child<'a> = (&'a SatisfiedPackage, String);
+
+struct TreePrinter<'o, F> {
+  children: F,
+  options: &'o TreeOptions,
+  // Plugins printed with their dependencies, and how many levels below them
+  // were printed.  --depth can cut a plugin short in one place and leave room
+  // for more of it in another.
+  printed: HashMap<(String, String), usize>,
+  lines: Vec<String>,
+}
+
+impl<'a, F> TreePrinter<'_, F>
+  where F: Fn(&'a SatisfiedPackage) -> Vec<Child<'a>>,
+{
+
+  fn visit(
+    &mut self,
+    package: &'a SatisfiedPackage,
+    label: String,
+    // What goes before the connector on this line, and before everything on
+    // the lines of the children.
+    indent: &str,
+    connector: &str,
+    child_indent: &str,
+    level: usize,
+  ) {
+    let below = self
+      .options
+      .depth
+      .map_or(usize::MAX, |depth| depth.saturating_sub(level));
+    let children = if below == 0 {
+      vec!()
+    } else {
+      (self.children)(package)
+    };
+    // Our inputs are printed whole even if they were printed under another.
+    let key = (package.name.clone(), package.version.to_string());
+    let printed = self.printed.get(&key).is_some_and(|b| *b >= below);
+    if printed && !children.is_empty() && level > 0 {
+      self.lines.push(format!("{}{}{} (*)", indent, connector, label));
+      return;
+    }
+    if !children.is_empty() {
+      self.printed.insert(key, below);
+    }
+    self.lines.push(format!("{}{}{}", indent, connector, label));
+    let indent = format!("{}{}", indent, child_indent);
+    let count = children.len();
+    for (i, (child, label)) in children.into_iter().enumerate() {
+      let last = i + 1 == count;
+      self.visit(
+        child,
+        label,
+        &indent,
+        if last { "└── " } else { "├── " },
+        if last { "    " } else { "│   " },
+        level + 1,
+      );
+    }
+  }
+
+}
+
+fn print<'a, F>(
+  tops: Vec<Child<'a>>,
+  children: F,
+  options: &TreeOptions,
+) -> String
+  where F: Fn(&'a SatisfiedPackage) -> Vec<Child<'a>>,
+{
+  let mut printer = TreePrinter {
+    children,
+    options,
+    printed: HashMap::new(),
+    lines: vec!(),
+  };
+  for (package, label) in tops {
+    printer.visit(package, label, "", "", "", 0);
+  }
+  printer.lines.join("\n")
+}
+
+// How what was asked for compares to what it got, if they differ.
+fn asked(
+  verb: &str,
+  requested: &JenkinsPluginVersion,
+  resolved: &JenkinsPluginVersion,
+  color: bool,
+) -> String {
+  let text = format!(" ({} {})", verb, requested);
+  match (color, requested.cmp(resolved)) {
+    (_, std::cmp::Ordering::Equal) => String::new(),
+    (false, _) => text,
+    (true, std::cmp::Ordering::Less) => text.yellow().to_string(),
+    (true, std::cmp::Ordering::Greater) => text.red().to_string(),
+  }
+}
+
+fn label(package: &SatisfiedPackage, options: &TreeOptions) -> String {
+  format!(
+    "{} {}{}",
+    package.name,
+    package.version,
+    asked("asked for", &package.requested, &package.version, options.color),
+  )
+}
+
+// Our inputs at the top, and what they depend on below them.
+pub fn tree(
+  graph: &[SatisfiedPackage],
+  filter: &EdgeFilter,
+  options: &TreeOptions,
+) -> String {
+  print(
+    graph.iter().map(|p| (p, label(p, options))).collect(),
+    |package| {
+      package
+        .dependencies
+        .iter()
+        .filter(|d| filter.follows(d))
+        .map(|d| (d, label(d, options)))
+        .collect()
+    },
+    options,
+  )
+}
+
+// The named plugin at the top, and what depends on it below, up to our inputs.
+// Each dependent says what it asks for of the plugin above it.
+pub fn tree_inverted(
+  graph: &[SatisfiedPackage],
+  filter: &EdgeFilter,
+  name: &str,
+  options: &TreeOptions,
+) -> Result<String, AppError> {
+  let mut dependents: HashMap<(String, String), Vec<Child>> = HashMap::new();
+  let mut tops: Vec<Child> = vec!();
+  for (dependent, dependency) in graph_edges(graph, filter) {
+    if dependency.name == name
+      && !tops.iter().any(|(p, _)| p.version == dependency.version) {
+      tops.push((
+        dependency,
+        format!("{} {}", dependency.name, dependency.version),
+      ));
+    }
+    if let Some(dependent) = dependent {
+      let existing = dependents
+        .entry((dependency.name.clone(), dependency.version.to_string()))
+        .or_default();
+      if !existing.iter().any(|(p, _)| {
+        p.name == dependent.name && p.version == dependent.version
+      }) {
+        existing.push((
+          dependent,
+          format!(
+            "{} {}{}",
+            dependent.name,
+            dependent.version,
+            asked(
+              "asks for",
+              &dependency.requested,
+              &dependency.version,
+              options.color,
+            ),
+          ),
+        ));
+      }
+    }
+  }
+  if tops.is_empty() {
+    return Err(AppError::PluginNotResolvedError(name.to_string()));
+  }
+  Ok(print(
+    tops,
+    |package| {
+      dependents
+        .get(&(package.name.clone(), package.version.to_string()))
+        .cloned()
+        .unwrap_or_default()
+    },
+    options,
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+
+  use crate::{cli::OptionalDependencies, input::satisfied};
+
+  use super::*;
+
+  fn options(depth: Option<usize>) -> TreeOptions {
+    TreeOptions { depth, color: false }
+  }
+
+  // ta -> tb -> ts -> tt, and ta -> ts.
+  fn graph() -> Vec<SatisfiedPackage> {
+    let ts = || satisfied("ts", "1.0", vec!(satisfied("tt", "1.0", vec!())));
+    vec!(satisfied("ta", "1.0", vec!(satisfied("tb", "1.0", vec!(ts())), ts())))
+  }
+
+  fn printed(graph: &[SatisfiedPackage], depth: Option<usize>) -> String {
+    let filter = EdgeFilter::new(graph, OptionalDependencies::All);
+    tree(graph, &filter, &options(depth))
+  }
+
+  #[test]
+  fn repeats_are_marked() {
+    assert_eq!(printed(&graph(), None), [
+      "ta 1.0",
+      "├── tb 1.0",
+      "│   └── ts 1.0",
+      "│       └── tt 1.0",
+      "└── ts 1.0 (*)",
+    ].join("\n"));
+  }
+
+  #[test]
+  fn depth_doesnt_hide_repeats() {
+    assert_eq!(printed(&graph(), Some(2)), [
+      "ta 1.0",
+      "├── tb 1.0",
+      "│   └── ts 1.0",
+      "└── ts 1.0",
+      "    └── tt 1.0",
+    ].join("\n"));
+    assert_eq!(printed(&graph(), Some(1)), [
+      "ta 1.0",
+      "├── tb 1.0",
+      "└── ts 1.0",
+    ].join("\n"));
+  }
+
+  #[test]
+  fn asked_for() {
+    let graph = vec!(satisfied("ta", "1.0", vec!(SatisfiedPackage {
+      requested: JenkinsPluginVersion::parse("0.9").unwrap(),
+      ..satisfied("tb", "1.0", vec!())
+    })));
+    assert_eq!(printed(&graph, None), [
+      "ta 1.0",
+      "└── tb 1.0 (asked for 0.9)",
+    ].join("\n"));
+  }
+
+  #[test]
+  fn inverted() {
+    let graph = graph();
+    let filter = EdgeFilter::new(&graph, OptionalDependencies::All);
+    assert_eq!(
+      tree_inverted(&graph, &filter, "tt", &options(None)).unwrap(),
+      [
+        "tt 1.0",
+        "└── ts 1.0",
+        "    ├── tb 1.0",
+        "    │   └── ta 1.0",
+        "    └── ta 1.0",
+      ].join("\n"),
+    );
+    assert!(tree_inverted(&graph, &filter, "tz", &options(None)).is_err());
+  }
+
+}
@@ -0,0 +1,314 @@
+// The Jenkins update center publishes metadata about plugins as JSON:
+//
+// - update-center.json has the latest version of each plugin.  The copy served
+//   to Jenkins is wrapped in "updateCenter.post(...);" and
+//   update-center.actual.json is the same without the wrapping.
+// - plugin-versions.json has every released version of each plugin.
+//
+// Both can be read from a local file or a URL.  They are large, so a remote
+// copy is kept in the cache directory, and only downloaded again once the
+// server says it has changed.
+//
+// Both also publish a sha256 for every archive, base64 encoded, which we check
+// our downloads against.
+
+use std::collections::HashMap;
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+use log::*;
+use reqwest::{blocking, header, StatusCode};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{error::AppError, jenkins_plugin_version::JenkinsPluginVersion};
+
+fn is_remote(location: &str) -> bool {
+  location.starts_with("http://") || location.starts_with("https://")
+}
+
+// Where a remote copy is cached.  The file name keeps the end of the URL so it
+// can be found by eye, but the whole URL picks the file, so two documents that
+// happen to share a name don't share a copy.
+fn update_center_cache_path(cache_dir: &str, location: &str) -> String {
+  let file_name = location
+    .rsplit("/")
+    .next()
+    .filter(|name| !name.is_empty())
+    .unwrap_or("update-center.json");
+  let digest = format!("{:x}", Sha256::digest(location.as_bytes()));
+  format!("{}/{}--{}", cache_dir, &digest[..16], file_name)
+}
+
+// The ETag and Last-Modified of the cached copy, kept beside it as
+// "Name: value" lines.
+fn update_center_validators_path(cache_path: &str) -> String {
+  format!("{}.validators", cache_path)
+}
+
+// A remote document is fetched again on every run, but with the validators of
+// the cached copy, so an unchanged document costs a 304 rather than a
+// download.  If the fetch fails the cached copy is used, stale or not, so runs
+// keep working offline.
+pub fn update_center_text(
+  cache_dir: &str,
+  location: &str,
+) -> Result<String, AppError> {
+  if !is_remote(location) {
+    return std::fs::read_to_string(location)
+      .map_err(|e| AppError::UpdateCenterReadError(location.to_string(), e));
+  }
+  let cache_path = update_center_cache_path(cache_dir, location);
+  let validators_path = update_center_validators_path(&cache_path);
+  let cached = std::fs::read_to_string(&cache_path).ok();
+  let mut request = blocking::Client::new().get(location);
+  if cached.is_some() {
+    let validators = std::fs::read_to_string(&validators_path)
+      .unwrap_or_default();
+    for line in validators.lines() {
+      match line.split_once(": ") {
+        Some(("ETag", etag)) => {
+          request = request.header(header::IF_NONE_MATCH, etag);
+        },
+        Some(("Last-Modified", modified)) => {
+          request = request.header(header::IF_MODIFIED_SINCE, modified);
+        },
+        _ => (),
+      }
+    }
+  }
+  info!("Trying url: {}", location);
+  let response = request
+    .send()
+    .and_then(|response| response.error_for_status())
+    .map_err(|e| AppError::UpdateCenterGetError(
+      location.to_string(),
+      e.to_string(),
+    ));
+  match (response, cached) {
+    (Ok(response), Some(text))
+      if response.status() == StatusCode::NOT_MODIFIED => {
+      info!("{} is unchanged, using {}.", location, cache_path);
+      Ok(text)
+    },
+    (Ok(response), _) => {
+      let validators = [
+        ("ETag", header::ETAG),
+        ("Last-Modified", header::LAST_MODIFIED),
+      ]
+        .into_iter()
+        .filter_map(|(name, key)| {
+          let value = response.headers().get(key)?.to_str().ok()?;
+          Some(format!("{}: {}\n", name, value))
+        })
+        .collect::<String>();
+      let text = response
+        .text()
+        .map_err(|e| AppError::UpdateCenterGetError(
+          location.to_string(),
+          e.to_string(),
+        ))
+        ?;
+      for (path, contents) in [
+        (&cache_path, &text),
+        (&validators_path, &validators),
+      ] {
+        match std::fs::write(path, contents) {
+          Ok(_) => info!("Wrote {} to: {}", location, path),
+          Err(e) => warn!(
+            "Error writing {}.  Non-panic error due to caching nature.  Error: {}",
+            path,
+            e,
+          ),
+        };
+      }
+      Ok(text)
+    },
+    (Err(e), Some(text)) => {
+      warn!(
+        "Could not refresh {}, using the cached copy in {}.  Error: {:?}",
+        location,
+        cache_path,
+        e,
+      );
+      Ok(text)
+    },
+    (Err(e), None) => Err(e),
+  }
+}
+
+// Published sha256 digests, keyed by plugin name and then version.
+pub struct Checksums {
+  pub sha256: HashMap<String, HashMap<String, String>>,
+}
+
+impl Checksums {
+
+  // Check a digest we computed (as hex) against the published one.  Plugins
+  // that aren't published at all (such as in-house plugins on a private
+  // mirror) only get a warning, since there's nothing to compare against.  A
+  // published plugin without this version is an error though, since that's
+  // what an archive nobody released looks like.  update-center.json only has
+  // the latest version of each plugin, so pinning anything older needs
+  // plugin-versions.json.
+  pub fn verify(
+    &self,
+    name: &str,
+    version: &JenkinsPluginVersion,
+    hex_digest: &str,
+  ) -> Result<(), AppError> {
+    let versions = match self.sha256.get(name) {
+      Some(versions) => versions,
+      None => {
+        warn!("No published checksums for {}.", name);
+        return Ok(());
+      },
+    };
+    match versions.get(&version.to_string()) {
+      None => Err(AppError::ChecksumMissingError(
+        name.to_string(),
+        version.to_string(),
+      )),
+      Some(expected) => {
+        let actual = hex_to_base64(hex_digest);
+        if *expected == actual {
+          debug!("Checksum for {} {} matches.", name, version);
+          Ok(())
+        } else {
+          Err(AppError::ChecksumMismatchError(
+            name.to_string(),
+            version.to_string(),
+            expected.clone(),
+            actual,
+          ))
+        }
+      },
+    }
+  }
+
+}
+
+pub fn hex_to_base64(hex: &str) -> String {
+  let bytes = (0..hex.len())
+    .step_by(2)
+    .filter_map(|i| hex.get(i..i + 2))
+    .filter_map(|pair| u8::from_str_radix(pair, 16).ok())
+    .collect::<Vec<u8>>();
+  BASE64_STANDARD.encode(bytes)
+}
+
+// Either document works.  In update-center.json each plugin maps straight to
+// one entry, and in plugin-versions.json each plugin maps to its versions.
+pub fn checksums_load(
+  cache_dir: &str,
+  location: &str,
+) -> Result<Checksums, AppError> {
+  let text = update_center_text(cache_dir, location)?;
+  let json = text
+    .trim()
+    .strip_prefix("updateCenter.post(")
+    .map(|rest| rest.trim_end_matches(";").trim_end_matches(")"))
+    .unwrap_or(&text);
+  let document: Value = serde_json::from_str(json)
+    .map_err(|e| {
+      AppError::UpdateCenterDeserializeError(location.to_string(), e)
+    })?;
+  let entry_sha256 = |entry: &Value| -> Option<(String, String)> {
+    Some((
+      entry.get("version")?.as_str()?.to_string(),
+      entry.get("sha256")?.as_str()?.to_string(),
+    ))
+  };
+  let sha256 = document
+    .get("plugins")
+    .and_then(|plugins| plugins.as_object())
+    .map(|plugins| {
+      plugins
+        .iter()
+        .map(|(name, plugin)| {
+          let versions = if plugin.get("version").is_some_and(|v| v.is_string()) {
+            entry_sha256(plugin).into_iter().collect()
+          } else {
+            plugin
+              .as_object()
+              .map(|versions| {
+                versions.values().filter_map(entry_sha256).collect()
+              })
+              .unwrap_or_default()
+          };
+          (name.clone(), versions)
+        })
+        .collect()
+    })
+    .unwrap_or_default();
+  Ok(Checksums { sha256 })
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn checksums() -> Checksums {
+    Checksums {
+      sha256: HashMap::from([(
+        "git".to_string(),
+        HashMap::from([(
+          "5.2.2".to_string(),
+          hex_to_base64(&"ab".repeat(32)),
+        )]),
+      )]),
+    }
+  }
+
+  fn verify(
+    name: &str,
+    version: &str,
+    hex_digest: &str,
+  ) -> Result<(), AppError> {
+    checksums().verify(
+      name,
+      &JenkinsPluginVersion::parse(version).unwrap(),
+      hex_digest,
+    )
+  }
+
+  #[test]
+  fn base64_digests() {
+    // The sha256 of nothing.
+    assert_eq!(
+      hex_to_base64(
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+      ),
+      "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+    );
+    assert_eq!(hex_to_base64("00ff10"), "AP8Q");
+    assert_eq!(hex_to_base64(""), "");
+  }
+
+  #[test]
+  fn verify_matches() {
+    assert!(verify("git", "5.2.2", &"ab".repeat(32)).is_ok());
+  }
+
+  #[test]
+  fn verify_mismatch() {
+    assert!(matches!(
+      verify("git", "5.2.2", &"cd".repeat(32)),
+      Err(AppError::ChecksumMismatchError(..)),
+    ));
+  }
+
+  #[test]
+  fn verify_unlisted_version() {
+    assert!(matches!(
+      verify("git", "5.2.1", &"ab".repeat(32)),
+      Err(AppError::ChecksumMissingError(..)),
+    ));
+  }
+
+  #[test]
+  fn verify_unlisted_plugin() {
+    assert!(verify("in-house", "1.0", &"ab".repeat(32)).is_ok());
+  }
+
+}
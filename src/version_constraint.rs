@@ -0,0 +1,314 @@
+// Input files can ask for more than an exact version.  A constraint is one of:
+//
+// - An exact version, such as "1.2.3" or "= 1.2.3".
+// - "latest", which takes whatever the newest published version is.
+// - One or two comma separated bounds, such as ">= 2.3", "< 3.0", or
+//   ">= 2.3, < 3.0".
+//
+// Anything other than an exact version needs a listing of published versions
+// to pick from, which is why resolving a constraint takes the candidates as an
+// argument.
+
+use std::fmt::{Display, Formatter};
+
+use serde::{de::{self, Visitor}, Deserialize, Deserializer};
+
+use crate::{error::AppError, jenkins_plugin_version::JenkinsPluginVersion};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VersionConstraint {
+  Exact(JenkinsPluginVersion),
+  Latest,
+  Between(VersionConstraintBetween),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VersionConstraintBetween {
+  pub version_lower_bound: Option<VersionBound>,
+  pub version_upper_bound: Option<VersionBound>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct VersionBound {
+  pub version: JenkinsPluginVersion,
+  pub inclusive: bool,
+}
+
+impl VersionConstraint {
+
+  pub fn parse(s: &str) -> Result<Self, AppError> {
+    let trimmed = s.trim();
+    if trimmed.eq_ignore_ascii_case("latest") {
+      return Ok(VersionConstraint::Latest);
+    }
+    let comparators = trimmed
+      .split(",")
+      .map(|c| c.trim())
+      .collect::<Vec<&str>>();
+    match comparators.as_slice() {
+      [single] if !single.starts_with(['<', '>']) => {
+        let version = single.trim_start_matches('=');
+        constraint_version(s, version).map(VersionConstraint::Exact)
+      },
+      _ => {
+        let mut between = VersionConstraintBetween {
+          version_lower_bound: None,
+          version_upper_bound: None,
+        };
+        for comparator in comparators {
+          let (operator, version) = comparator
+            .find(|c: char| !matches!(c, '<' | '>' | '='))
+            .map(|i| comparator.split_at(i))
+            .ok_or_else(|| {
+              AppError::VersionConstraintParseError(s.to_string())
+            })?;
+          let bound = VersionBound {
+            version: constraint_version(s, version)?,
+            inclusive: operator.ends_with('='),
+          };
+          let slot = match operator {
+            ">" | ">=" => &mut between.version_lower_bound,
+            "<" | "<=" => &mut between.version_upper_bound,
+            _ => return Err(AppError::VersionConstraintParseError(s.to_string())),
+          };
+          // Two lower or two upper bounds is almost certainly a typo.
+          if slot.is_some() {
+            return Err(AppError::VersionConstraintParseError(s.to_string()));
+          }
+          *slot = Some(bound);
+        }
+        Ok(VersionConstraint::Between(between))
+      },
+    }
+  }
+
+  pub fn satisfied_by(&self, version: &JenkinsPluginVersion) -> bool {
+    match self {
+      VersionConstraint::Exact(v) => v == version,
+      VersionConstraint::Latest => true,
+      VersionConstraint::Between(between) => {
+        let above = match &between.version_lower_bound {
+          Some(b) if b.inclusive => version >= &b.version,
+          Some(b) => version > &b.version,
+          None => true,
+        };
+        let below = match &between.version_upper_bound {
+          Some(b) if b.inclusive => version <= &b.version,
+          Some(b) => version < &b.version,
+          None => true,
+        };
+        above && below
+      },
+    }
+  }
+
+  // Pick the highest candidate that satisfies the constraint, if any.
+  pub fn select<'a, I>(&self, candidates: I) -> Option<JenkinsPluginVersion>
+    where I: IntoIterator<Item = &'a JenkinsPluginVersion>,
+  {
+    candidates
+      .into_iter()
+      .filter(|v| self.satisfied_by(v))
+      .max()
+      .cloned()
+  }
+
+}
+
+// The version part of a comparator.  Whitespace or another operator in there
+// means a comma went missing, as in ">= 2.3 < 3.0", which would otherwise
+// parse as a lower bound of "2.3 < 3.0".
+fn constraint_version(
+  constraint: &str,
+  version: &str,
+) -> Result<JenkinsPluginVersion, AppError> {
+  let version = version.trim();
+  if version.contains(|c: char| c.is_whitespace() || "<>=".contains(c)) {
+    return Err(AppError::VersionConstraintParseError(constraint.to_string()));
+  }
+  JenkinsPluginVersion::parse(version)
+    .map_err(|_| AppError::VersionConstraintParseError(constraint.to_string()))
+}
+
+impl Display for VersionConstraint {
+
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    match self {
+      VersionConstraint::Exact(v) => write!(f, "{}", v),
+      VersionConstraint::Latest => f.write_str("latest"),
+      VersionConstraint::Between(between) => {
+        let lower = between.version_lower_bound.as_ref().map(|b| {
+          format!("{} {}", if b.inclusive { ">=" } else { ">" }, b.version)
+        });
+        let upper = between.version_upper_bound.as_ref().map(|b| {
+          format!("{} {}", if b.inclusive { "<=" } else { "<" }, b.version)
+        });
+        f.write_str(
+          &lower
+            .into_iter()
+            .chain(upper)
+            .collect::<Vec<String>>()
+            .join(", ")
+        )
+      },
+    }
+  }
+
+}
+
+impl<'de> Deserialize<'de> for VersionConstraint {
+  fn deserialize<D>(deserializer: D) -> Result<VersionConstraint, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    deserializer.deserialize_string(VersionConstraintVisitor)
+  }
+}
+
+struct VersionConstraintVisitor;
+
+impl<'de> Visitor<'de> for VersionConstraintVisitor {
+  type Value = VersionConstraint;
+
+  fn expecting(&self, formatter: &mut Formatter) -> std::fmt::Result {
+    formatter.write_str(
+      "a version, \"latest\", or comma separated bounds like \">= 2.3, < 3.0\"",
+    )
+  }
+
+  fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+  where E: de::Error,
+  {
+    VersionConstraint::parse(value)
+      .map_err(|_| {
+        E::custom(format!("invalid value for VersionConstraint: {}", value))
+      })
+  }
+
+  fn visit_string<E>(self, value: String) -> Result<Self::Value, E>
+  where E: de::Error,
+  {
+    self.visit_str(&value)
+  }
+
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn v(s: &str) -> JenkinsPluginVersion {
+    JenkinsPluginVersion::parse(s).unwrap()
+  }
+
+  fn bound(version: &str, inclusive: bool) -> Option<VersionBound> {
+    Some(VersionBound { version: v(version), inclusive })
+  }
+
+  fn between(
+    lower: Option<VersionBound>,
+    upper: Option<VersionBound>,
+  ) -> VersionConstraint {
+    VersionConstraint::Between(VersionConstraintBetween {
+      version_lower_bound: lower,
+      version_upper_bound: upper,
+    })
+  }
+
+  #[test]
+  fn exact() {
+    for s in ["1.2.3", "= 1.2.3", "=1.2.3", " 1.2.3 "] {
+      assert_eq!(
+        VersionConstraint::parse(s).unwrap(),
+        VersionConstraint::Exact(v("1.2.3")),
+        "{:?}",
+        s,
+      );
+    }
+  }
+
+  #[test]
+  fn latest() {
+    assert_eq!(
+      VersionConstraint::parse("Latest").unwrap(),
+      VersionConstraint::Latest,
+    );
+  }
+
+  #[test]
+  fn bounds() {
+    assert_eq!(
+      VersionConstraint::parse(">= 2.3").unwrap(),
+      between(bound("2.3", true), None),
+    );
+    assert_eq!(
+      VersionConstraint::parse("<3.0").unwrap(),
+      between(None, bound("3.0", false)),
+    );
+    assert_eq!(
+      VersionConstraint::parse(">= 2.3, < 3.0").unwrap(),
+      between(bound("2.3", true), bound("3.0", false)),
+    );
+    assert_eq!(
+      VersionConstraint::parse("<= 3.0,> 2.3").unwrap(),
+      between(bound("2.3", false), bound("3.0", true)),
+    );
+  }
+
+  #[test]
+  fn invalid() {
+    for s in [
+      "",
+      ">= 2.3 < 3.0",
+      ">= 2.3 3.0",
+      "1.2 3",
+      "=> 2.3",
+      ">>= 2.3",
+      ">= 2.3, >= 2.4",
+      "< 3.0, <= 4.0",
+      ">=",
+      ">= 2.3,",
+    ] {
+      assert!(VersionConstraint::parse(s).is_err(), "{:?}", s);
+    }
+  }
+
+  #[test]
+  fn satisfied_by() {
+    let constraint = VersionConstraint::parse(">= 2.3, < 3.0").unwrap();
+    assert!(!constraint.satisfied_by(&v("2.2")));
+    assert!(constraint.satisfied_by(&v("2.3")));
+    assert!(constraint.satisfied_by(&v("2.10")));
+    assert!(!constraint.satisfied_by(&v("3.0")));
+    assert!(!constraint.satisfied_by(&v("3.0.1")));
+    let constraint = VersionConstraint::parse("> 2.3, <= 3.0").unwrap();
+    assert!(!constraint.satisfied_by(&v("2.3")));
+    assert!(constraint.satisfied_by(&v("3.0")));
+    assert!(VersionConstraint::parse("1.0").unwrap().satisfied_by(&v("1")));
+  }
+
+  #[test]
+  fn select() {
+    let candidates = ["1.0", "2.3", "2.9", "3.0"].map(v);
+    let select = |s: &str| {
+      VersionConstraint::parse(s)
+        .unwrap()
+        .select(&candidates)
+        .map(|v| v.to_string())
+    };
+    assert_eq!(select("latest").as_deref(), Some("3.0"));
+    assert_eq!(select(">= 2.3, < 3.0").as_deref(), Some("2.9"));
+    assert_eq!(select("2.3").as_deref(), Some("2.3"));
+    assert_eq!(select("> 3.0"), None);
+  }
+
+  #[test]
+  fn display() {
+    assert_eq!(
+      VersionConstraint::parse(">=2.3,<3.0").unwrap().to_string(),
+      ">= 2.3, < 3.0",
+    );
+  }
+
+}
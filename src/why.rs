@@ -0,0 +1,73 @@
+// Reviewers want to know why a plugin they never asked for gets installed.  The
+// answer is every path from our input down to it, with what each step asked
+// for along the way.
+
+use std::fmt::{Display, Formatter};
+
+use crate::{
+  error::AppError,
+  input::{EdgeFilter, SatisfiedPackage},
+};
+
+// A path from one of our inputs down to the plugin in question.  Each step is
+// the package at the far end of an edge, so the first one is an input.
+pub struct WhyChain<'a> {
+  pub steps: Vec<&'a SatisfiedPackage>,
+}
+
+impl Display for WhyChain<'_> {
+
+  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    let steps = self
+      .steps
+      .iter()
+      .map(|step| {
+        format!(
+          "{} {} (asked for {}{})",
+          step.name,
+          step.version,
+          step.requested,
+          if step.optional { ", optional" } else { "" },
+        )
+      })
+      .collect::<Vec<String>>();
+    write!(f, "{}", steps.join(" -> "))
+  }
+
+}
+
+// Every chain from our input to the named plugin, following only the edges the
+// filter does.  A chain ends at the first occurrence of the plugin, even if it
+// depends on itself further down.
+pub fn why<'a>(
+  graph: &'a [SatisfiedPackage],
+  filter: &EdgeFilter,
+  name: &str,
+) -> Result<Vec<WhyChain<'a>>, AppError> {
+  fn visit<'a>(
+    package: &'a SatisfiedPackage,
+    filter: &EdgeFilter,
+    name: &str,
+    path: &mut Vec<&'a SatisfiedPackage>,
+    chains: &mut Vec<WhyChain<'a>>,
+  ) {
+    path.push(package);
+    if package.name == name {
+      chains.push(WhyChain { steps: path.clone() });
+    } else {
+      for dependency in package.dependencies.iter().filter(|d| filter.follows(d)) {
+        visit(dependency, filter, name, path, chains);
+      }
+    }
+    path.pop();
+  }
+  let mut chains = vec!();
+  for root in graph {
+    visit(root, filter, name, &mut vec!(), &mut chains);
+  }
+  if chains.is_empty() {
+    Err(AppError::PluginNotResolvedError(name.to_string()))
+  } else {
+    Ok(chains)
+  }
+}